@@ -0,0 +1,213 @@
+//! Known capabilities, grouped by the element declaring them in the application manifest.
+//!
+//! See [App capability declarations](https://docs.microsoft.com/en-us/windows/uwp/packaging/app-capability-declarations).
+//!
+use std::error::Error;
+
+/// Capabilities declared through `<Capability>` in the foundation namespace.
+const FOUNDATION: &[&str] = &[
+    "internetClient",
+    "internetClientServer",
+    "privateNetworkClientServer",
+    "allJoyn",
+    "codeGeneration",
+];
+
+/// Capabilities declared through `<uap:Capability>`.
+const UAP: &[&str] = &[
+    "musicLibrary",
+    "picturesLibrary",
+    "videosLibrary",
+    "documentsLibrary",
+    "removableStorage",
+    "appointments",
+    "contacts",
+    "phoneCall",
+    "phoneCallHistoryPublic",
+    "blockedChatMessages",
+    "chat",
+    "objects3D",
+    "voipCall",
+    "userAccountInformation",
+    "enterpriseAuthentication",
+    "sharedUserCertificates",
+];
+
+/// Restricted capabilities, declared through `<rescap:Capability>`. Submitting an
+/// application declaring any of these to the Microsoft Store requires approval.
+const RESTRICTED: &[&str] = &[
+    "accessoryManager",
+    "allAppMods",
+    "allowElevation",
+    "appBroadcastServices",
+    "appCaptureServices",
+    "appCaptureSettings",
+    "appDiagnostics",
+    "appLicensing",
+    "appointmentsSystem",
+    "audioDeviceConfiguration",
+    "backgroundMediaRecording",
+    "backgroundVoIP",
+    "broadFileSystemAccess",
+    "cameraProcessingExtension",
+    "cellularDeviceControl",
+    "cellularDeviceIdentity",
+    "cellularMessaging",
+    "chatSystem",
+    "confirmAppClose",
+    "contactsSystem",
+    "cortanaPermissions",
+    "cortanaSpeechAccessory",
+    "customInstallActions",
+    "deviceManagementDmAccount",
+    "deviceManagementEmailAccount",
+    "deviceManagementFoundation",
+    "deviceManagementWapSecurityPolicies",
+    "devicePortalProvider",
+    "deviceUnlock",
+    "dualSimTiles",
+    "email",
+    "emailSystem",
+    "enterpriseDataPolicy",
+    "enterpriseDeviceLockdown",
+    "expandedResources",
+    "extendedBackgroundTaskTime",
+    "extendedExecutionBackgroundAudio",
+    "extendedExecutionCritical",
+    "extendedExecutionUnconstrained",
+    "firstSignInSettings",
+    "gameBarServices",
+    "gameList",
+    "gameMonitor",
+    "inputForegroundObservation",
+    "inputInjectionBrokered",
+    "inputObservation",
+    "inputSuppression",
+    "interopServices",
+    "localSystemServices",
+    "locationHistory",
+    "locationSystem",
+    "networkConnectionManagerProvisioning",
+    "networkDataPlanProvisioning",
+    "networkingVpnProvider",
+    "oemDeployment",
+    "oemPublicDirectory",
+    "packagedServices",
+    "packageManagement",
+    "packagePolicySystem",
+    "packageQuery",
+    "phoneCallHistory",
+    "phoneCallHistorySystem",
+    "previewInkWorkspace",
+    "previewPenWorkspace",
+    "previewStore",
+    "previewUiComposition",
+    "protectedApp",
+    "remotePassportAuthentication",
+    "runFullTrust",
+    "screenDuplication",
+    "secondaryAuthenticationFactor",
+    "secureAssessment",
+    "slapiQueryLicenseValue",
+    "smsSend",
+    "startScreenManagement",
+    "storeLicenseManagement",
+    "targetedContent",
+    "teamEditionDeviceCredential",
+    "teamEditionExperience",
+    "teamEditionView",
+    "uiAutomation",
+    "unvirtualizedResources",
+    "userDataAccountsProvider",
+    "userDataSystem",
+    "userPrincipalName",
+    "userSystemId",
+    "walletSystem",
+    "xboxAccessoryManagement",
+];
+
+/// Capabilities declared through `<DeviceCapability>`.
+const DEVICE: &[&str] = &[
+    "activity",
+    "bluetooth",
+    "gazeInput",
+    "humanPresence",
+    "location",
+    "lowLevel",
+    "microphone",
+    "optical",
+    "pointOfService",
+    "proximity",
+    "radios",
+    "webcam",
+    "wiFiControl",
+];
+
+/// Fails if any of `capabilities`, taken from the metadata `key`, isn't a known general or
+/// restricted capability, or if `device` is set, a known device capability.
+///
+pub fn validate(key: &str, capabilities: &[String], device: bool) -> Result<(), Box<dyn Error>> {
+    for capability in capabilities {
+        let (known, other) = if device {
+            (is_device(capability), is_general(capability))
+        } else {
+            (is_general(capability), is_device(capability))
+        };
+        if !known {
+            let hint = match (other, device) {
+                (true, true) => " (it is a general capability)",
+                (true, false) => " (it is a device capability)",
+                (false, _) => "",
+            };
+            return Err(format!(
+                "Invalid '{}' key: Unknown capability '{}'{}",
+                key, capability, hint
+            )
+            .into());
+        }
+    }
+
+    Ok(())
+}
+
+/// Returns whether `capability` requires approval for Microsoft Store submission.
+///
+pub fn is_restricted(capability: &str) -> bool {
+    RESTRICTED.contains(&capability)
+}
+
+/// Renders the `<Capabilities>` element declaring `capabilities` and `device_capabilities`
+/// in the order required by the schema, or an empty string if there are none. Unknown
+/// names are skipped.
+///
+pub fn render(capabilities: &[String], device_capabilities: &[String]) -> String {
+    let mut elements = String::new();
+    let groups = [
+        (FOUNDATION, "Capability", capabilities),
+        (UAP, "uap:Capability", capabilities),
+        (RESTRICTED, "rescap:Capability", capabilities),
+        (DEVICE, "DeviceCapability", device_capabilities),
+    ];
+    for (known, element, declared) in groups.iter() {
+        for name in known.iter() {
+            if declared.iter().any(|declared| declared == name) {
+                elements.push_str(&format!("\n    <{} Name=\"{}\" />", element, name));
+            }
+        }
+    }
+    if elements.is_empty() {
+        return elements;
+    }
+
+    format!("\n  <Capabilities>{}\n  </Capabilities>", elements)
+}
+
+fn is_general(capability: &str) -> bool {
+    [FOUNDATION, UAP, RESTRICTED]
+        .iter()
+        .any(|known| known.contains(&capability))
+}
+
+fn is_device(capability: &str) -> bool {
+    DEVICE.contains(&capability)
+}
@@ -6,7 +6,7 @@ use std::{
     error::Error,
     fs,
     iter::once,
-    path::{Path, PathBuf},
+    path::{Path, PathBuf, MAIN_SEPARATOR_STR},
 };
 
 use toml::{value::Map, Value};
@@ -14,6 +14,10 @@ use toml::{value::Map, Value};
 mod shared;
 use shared::*;
 
+mod capabilities;
+mod template;
+use template::Variable;
+
 fn main() -> Result<(), Box<dyn Error>> {
     let cargo_config = get_cargo_config()?;
 
@@ -50,7 +54,8 @@ fn main() -> Result<(), Box<dyn Error>> {
 
     // Generate FileMapping.ini file from template
     let file_mapping = fs::read_to_string(&mapping_file)?;
-    let file_mapping = generate_mapping_file(file_mapping, &cargo_config, &cargo_pkg_config)?;
+    let file_mapping =
+        generate_mapping_file(file_mapping, &appx_config, &cargo_config, &cargo_pkg_config)?;
     let mut file_mapping_out = cargo_config.target_dir;
     file_mapping_out.push("FileMapping.ini");
     fs::write(&file_mapping_out, &file_mapping)?;
@@ -67,21 +72,7 @@ fn get_cargo_config() -> Result<CargoConfig, Box<dyn Error>> {
     // Find the cargo package root
     let package_root = PathBuf::from(env::var("CARGO_MANIFEST_DIR")?);
 
-    // Find location where the executable artifacts are compiled to. This should be
-    // at <cargo package root>\target\<target triple>\<debug|release>
-    let mut target_dir = PathBuf::from(env::var("OUT_DIR")?);
-    target_dir.pop();
-    target_dir.pop();
-    target_dir.pop();
-    let target_dir = target_dir;
-
-    // Issue a warning if the target directory doesn't match the expected pattern
-    if !target_dir.ends_with("debug") && !target_dir.ends_with("release") {
-        println!(
-            "cargo:warning=Unexpected target directory {}",
-            target_dir.display()
-        );
-    }
+    let target_dir = get_artifact_dir(&package_root)?;
 
     Ok(CargoConfig {
         package_root,
@@ -89,6 +80,41 @@ fn get_cargo_config() -> Result<CargoConfig, Box<dyn Error>> {
     })
 }
 
+/// Finds the location where the executable artifacts are compiled to. This is
+/// <target dir>/<target triple>/<profile>, where <profile> is "debug", "release", or the
+/// name of a custom profile.
+///
+fn get_artifact_dir(package_root: &Path) -> Result<PathBuf, Box<dyn Error>> {
+    // Cargo doesn't tell build scripts about locations such as `--artifact-dir`, so allow
+    // overriding the location explicitly
+    println!("cargo:rerun-if-env-changed={}", ARTIFACT_DIR_ENV);
+    if let Some(dir) = env::var_os(ARTIFACT_DIR_ENV) {
+        return Ok(package_root.join(dir));
+    }
+
+    // The output directory of this build script is located at
+    // <artifact dir>/build/<package name>-<hash>/out, regardless of profile, target
+    // directory, or workspace layout
+    let out_dir = PathBuf::from(env::var("OUT_DIR")?);
+    let mut ancestors = out_dir.ancestors();
+    match (
+        ancestors.next().and_then(Path::file_name),
+        ancestors.nth(1).and_then(Path::file_name),
+        ancestors.next(),
+    ) {
+        (Some(out), Some(build), Some(artifact_dir)) if out == "out" && build == "build" => {
+            Ok(artifact_dir.to_path_buf())
+        }
+        _ => Err(format!(
+            "Unexpected build script output directory {}; set {} to the directory the \
+             executable is compiled to",
+            out_dir.display(),
+            ARTIFACT_DIR_ENV
+        )
+        .into()),
+    }
+}
+
 enum Arch {
     X86,   // "i686-uwp-windows-msvc"
     X64,   // "x86_64-uwp-windows-msvc"
@@ -126,6 +152,8 @@ struct CargoPkgConfig {
     name: String,
     executable: String,
     arch: Arch,
+    /// Whether the profile is based on the `dev` profile
+    debug: bool,
 }
 
 fn get_cargo_pkg_config() -> Result<CargoPkgConfig, Box<dyn Error>> {
@@ -134,12 +162,14 @@ fn get_cargo_pkg_config() -> Result<CargoPkgConfig, Box<dyn Error>> {
     let mut executable = name.clone();
     executable.push_str(".exe");
     let arch: Arch = env::var("TARGET")?.try_into()?;
+    let debug = env::var("PROFILE")? == "debug";
 
     Ok(CargoPkgConfig {
         version,
         name,
         executable,
         arch,
+        debug,
     })
 }
 
@@ -149,10 +179,17 @@ struct AppxConfig {
     identity_publisher: String,
     identity_version: String,
     phone_product_id: Option<String>,
+    phone_publisher_id: Option<String>,
+    target_device_families: Option<Vec<TargetDeviceFamily>>,
+    package_dependencies: Option<Vec<PackageDependency>>,
     display_name: String,
     publisher_display_name: String,
     app_display_name: String,
     app_description: String,
+    capabilities: Vec<String>,
+    device_capabilities: Vec<String>,
+    /// Variables available to all templates
+    variables: Vec<Variable>,
 }
 
 fn get_appx_config(
@@ -226,6 +263,23 @@ fn get_appx_config(
         None
     };
 
+    // Extract phone publisher id; the template falls back to all zeros when missing
+    let phone_publisher_id = if let Some(id) = appxmanifest.get(PACKAGE_PHONE_PUBLISHER_ID_KEY) {
+        Some(
+            id.as_str()
+                .ok_or(format!("Invalid '{}' key", PACKAGE_PHONE_PUBLISHER_ID_KEY))?
+                .to_owned(),
+        )
+    } else {
+        None
+    };
+
+    // Extract target device families; the template provides a default when missing
+    let target_device_families = get_target_device_families(appxmanifest)?;
+
+    // Extract package dependencies; the template provides a default when missing
+    let package_dependencies = get_package_dependencies(appxmanifest, cargo_pkg_config.debug)?;
+
     // Extract display name; fall back to package name when missing
     let display_name = if let Some(name) = appxmanifest.get(PACKAGE_DISPLAY_NAME_KEY) {
         let name = name
@@ -266,18 +320,292 @@ fn get_appx_config(
         PACKAGE_VISUAL_DESCRIPTION_DEFAULT,
     );
 
+    // Extract capabilities; restricted capabilities require approval for Microsoft Store
+    // submission
+    let capabilities = get_string_array(appxmanifest, CAPABILITIES_KEY)?;
+    capabilities::validate(CAPABILITIES_KEY, &capabilities, false)?;
+    for capability in &capabilities {
+        if capabilities::is_restricted(capability) {
+            println!(
+                "cargo:warning=Capability '{}' is restricted and requires approval for \
+                 Microsoft Store submission",
+                capability
+            );
+        }
+    }
+    let device_capabilities = get_string_array(appxmanifest, DEVICE_CAPABILITIES_KEY)?;
+    capabilities::validate(DEVICE_CAPABILITIES_KEY, &device_capabilities, true)?;
+
+    let variables = get_template_variables(appxmanifest)?;
+
     Ok(AppxConfig {
         identity_name,
         identity_publisher,
         identity_version,
         phone_product_id,
+        phone_publisher_id,
+        target_device_families,
+        package_dependencies,
         display_name,
         publisher_display_name,
         app_display_name,
         app_description,
+        capabilities,
+        device_capabilities,
+        variables,
     })
 }
 
+#[derive(Debug)]
+struct TargetDeviceFamily {
+    name: String,
+    min_version: String,
+    max_version_tested: String,
+}
+
+impl Default for TargetDeviceFamily {
+    /// The device family targeted in the absence of a `target-device-families` key.
+    fn default() -> Self {
+        TargetDeviceFamily {
+            name: "Windows.Universal".to_owned(),
+            min_version: "10.0.10240.0".to_owned(),
+            max_version_tested: "10.0.18362.0".to_owned(),
+        }
+    }
+}
+
+/// Extracts the `target-device-families` array, or `None` if the key is missing. Versions
+/// need to consist of four numeric fields, and the minimum version must not exceed the
+/// maximum version tested.
+///
+fn get_target_device_families(
+    appxmanifest: &Map<String, Value>,
+) -> Result<Option<Vec<TargetDeviceFamily>>, Box<dyn Error>> {
+    let families = match appxmanifest.get(TARGET_DEVICE_FAMILIES_KEY) {
+        Some(families) => families.as_array().ok_or(format!(
+            "Invalid '{}' key: Expected an array of tables",
+            TARGET_DEVICE_FAMILIES_KEY
+        ))?,
+        None => return Ok(None),
+    };
+    if families.is_empty() {
+        return Err(format!(
+            "Invalid '{}' key: Expected at least one device family",
+            TARGET_DEVICE_FAMILIES_KEY
+        )
+        .into());
+    }
+
+    let mut result = Vec::<TargetDeviceFamily>::new();
+    for (index, family) in families.iter().enumerate() {
+        let key = format!("{}[{}]", TARGET_DEVICE_FAMILIES_KEY, index);
+        let family = family
+            .as_table()
+            .ok_or(format!("Invalid '{}' key: Expected a table", key))?;
+        let field = |name: &str| -> Result<String, Box<dyn Error>> {
+            Ok(family
+                .get(name)
+                .and_then(|val| val.as_str())
+                .ok_or(format!("Missing or invalid '{}.{}' key", key, name))?
+                .to_owned())
+        };
+        let name = field("name")?;
+        let min_version = field("min-version")?;
+        let max_version_tested = field("max-version-tested")?;
+
+        if name.is_empty() {
+            return Err(format!("Invalid '{}.name' key: Must not be empty", key).into());
+        }
+        if result.iter().any(|other| other.name == name) {
+            return Err(format!(
+                "Invalid '{}.name' key: Device family '{}' is listed more than once",
+                key, name
+            )
+            .into());
+        }
+        let min = parse_version(&min_version).ok_or(format!(
+            "Invalid '{}.min-version' key: Use four period-delimited numbers from 0 to 65535",
+            key
+        ))?;
+        let max = parse_version(&max_version_tested).ok_or(format!(
+            "Invalid '{}.max-version-tested' key: Use four period-delimited numbers from 0 \
+             to 65535",
+            key
+        ))?;
+        if min > max {
+            return Err(format!(
+                "Invalid '{}.max-version-tested' key: Must not be lower than '{}'",
+                key, min_version
+            )
+            .into());
+        }
+
+        result.push(TargetDeviceFamily {
+            name,
+            min_version,
+            max_version_tested,
+        });
+    }
+
+    Ok(Some(result))
+}
+
+#[derive(Debug)]
+struct PackageDependency {
+    name: String,
+    min_version: String,
+    publisher: String,
+}
+
+impl PackageDependency {
+    /// Returns the dependency named by `preset`, or `None` if there is no such preset. The
+    /// `vclibs` preset refers to the debug variant of the framework in `debug` builds.
+    ///
+    fn preset(preset: &str, debug: bool) -> Option<Self> {
+        let (name, min_version) = match preset {
+            "vclibs" if debug => ("Microsoft.VCLibs.140.00.Debug", "14.0.27810.0"),
+            "vclibs" => ("Microsoft.VCLibs.140.00", "14.0.27810.0"),
+            "vclibs-debug" => ("Microsoft.VCLibs.140.00.Debug", "14.0.27810.0"),
+            "microsoft-ui-xaml-2.7" => ("Microsoft.UI.Xaml.2.7", "7.2109.13004.0"),
+            "microsoft-ui-xaml-2.8" => ("Microsoft.UI.Xaml.2.8", "8.2208.12001.0"),
+            _ => return None,
+        };
+
+        Some(PackageDependency {
+            name: name.to_owned(),
+            min_version: min_version.to_owned(),
+            publisher: MICROSOFT_PUBLISHER.to_owned(),
+        })
+    }
+}
+
+/// Extracts the `package-dependencies` array, or `None` if the key is missing. Entries
+/// either name a preset, or are tables spelling out the dependency.
+///
+fn get_package_dependencies(
+    appxmanifest: &Map<String, Value>,
+    debug: bool,
+) -> Result<Option<Vec<PackageDependency>>, Box<dyn Error>> {
+    let dependencies = match appxmanifest.get(PACKAGE_DEPENDENCIES_KEY) {
+        Some(dependencies) => dependencies.as_array().ok_or(format!(
+            "Invalid '{}' key: Expected an array of preset names or tables",
+            PACKAGE_DEPENDENCIES_KEY
+        ))?,
+        None => return Ok(None),
+    };
+
+    let mut result = Vec::<PackageDependency>::new();
+    for (index, dependency) in dependencies.iter().enumerate() {
+        let key = format!("{}[{}]", PACKAGE_DEPENDENCIES_KEY, index);
+        let dependency = match dependency {
+            Value::String(preset) => PackageDependency::preset(preset, debug).ok_or(format!(
+                "Invalid '{}' key: Unknown preset '{}' (expected one of {})",
+                key,
+                preset,
+                PACKAGE_DEPENDENCY_PRESETS.join(", ")
+            ))?,
+            Value::Table(dependency) => {
+                let field = |name: &str| -> Result<String, Box<dyn Error>> {
+                    Ok(dependency
+                        .get(name)
+                        .and_then(|val| val.as_str())
+                        .filter(|val| !val.is_empty())
+                        .ok_or(format!("Missing or invalid '{}.{}' key", key, name))?
+                        .to_owned())
+                };
+                let dependency = PackageDependency {
+                    name: field("name")?,
+                    min_version: field("min-version")?,
+                    publisher: field("publisher")?,
+                };
+                if parse_version(&dependency.min_version).is_none() {
+                    return Err(format!(
+                        "Invalid '{}.min-version' key: Use four period-delimited numbers \
+                         from 0 to 65535",
+                        key
+                    )
+                    .into());
+                }
+                dependency
+            }
+            _ => {
+                return Err(
+                    format!("Invalid '{}' key: Expected a preset name or a table", key).into(),
+                )
+            }
+        };
+        if result.iter().any(|other| other.name == dependency.name) {
+            return Err(format!(
+                "Invalid '{}' key: Package '{}' is listed more than once",
+                key, dependency.name
+            )
+            .into());
+        }
+        result.push(dependency);
+    }
+
+    Ok(Some(result))
+}
+
+/// Parses a version of four period-delimited numeric fields.
+///
+fn parse_version(version: &str) -> Option<[u16; 4]> {
+    let fields = version
+        .split('.')
+        .map(|field| field.parse::<u16>().ok())
+        .collect::<Option<Vec<_>>>()?;
+
+    fields.try_into().ok()
+}
+
+/// Collects the variables available to all templates: Scalar metadata values named after
+/// their key, and the user-defined values of the `[package.metadata.appxmanifest.vars]`
+/// table.
+///
+fn get_template_variables(
+    appxmanifest: &Map<String, Value>,
+) -> Result<Vec<Variable>, Box<dyn Error>> {
+    let mut variables = appxmanifest
+        .iter()
+        .filter_map(|(key, value)| Some(Variable::new(key, key, &scalar_value(value)?)))
+        .collect::<Vec<_>>();
+
+    if let Some(vars) = appxmanifest.get(TEMPLATE_VARS_KEY) {
+        let vars = vars.as_table().ok_or(format!(
+            "Invalid '{}' key: Expected a table",
+            TEMPLATE_VARS_KEY
+        ))?;
+        for (name, value) in vars {
+            let key = format!("{}.{}", TEMPLATE_VARS_KEY, name);
+            if !template::is_valid_name(name) {
+                return Err(format!(
+                    "Invalid '{}' key: Use letters, digits, dashes, or underscores, other than \
+                     'else' or 'endif'",
+                    key
+                )
+                .into());
+            }
+            let value = scalar_value(value).ok_or(format!(
+                "Invalid '{}' key: Expected a string, number, or boolean",
+                key
+            ))?;
+            variables.push(Variable::new(name, &key, &value));
+        }
+    }
+
+    Ok(variables)
+}
+
+/// Returns the text of a string, number, or boolean `value`, or `None` otherwise.
+///
+fn scalar_value(value: &Value) -> Option<String> {
+    match value {
+        Value::String(value) => Some(value.clone()),
+        Value::Integer(_) | Value::Float(_) | Value::Boolean(_) => Some(value.to_string()),
+        _ => None,
+    }
+}
+
 fn get_value(manifest: &Map<String, Value>, key: &str) -> Result<String, Box<dyn Error>> {
     Ok(manifest
         .get(key)
@@ -286,92 +614,282 @@ fn get_value(manifest: &Map<String, Value>, key: &str) -> Result<String, Box<dyn
         .to_owned())
 }
 
+/// Returns the strings of the array `key`, or an empty list if the key is missing.
+///
+fn get_string_array(
+    manifest: &Map<String, Value>,
+    key: &str,
+) -> Result<Vec<String>, Box<dyn Error>> {
+    match manifest.get(key) {
+        None => Ok(Vec::new()),
+        Some(value) => value
+            .as_array()
+            .and_then(|values| {
+                values
+                    .iter()
+                    .map(|value| value.as_str().map(str::to_owned))
+                    .collect()
+            })
+            .ok_or_else(|| format!("Invalid '{}' key: Expected an array of strings", key).into()),
+    }
+}
+
 fn warn_if_default(key: &str, value: &str, default: &str) {
     if value == default {
         println!("cargo:warning=Metadata key '{}' uses default value", key);
     }
 }
 
+/// Generates the application manifest from the template. Values are XML-escaped
+/// according to where their placeholder appears (attribute value or element text), and
+/// the result is parsed back to catch malformed output early.
+///
 fn generate_appx_manifest(
     template: String,
     appx_config: &AppxConfig,
     cargo_pkg_config: &CargoPkgConfig,
 ) -> Result<String, Box<dyn Error>> {
-    // Insert disclaimer
-    let mut manifest = template.replace("$generated-content-disclaimer$", XML_DISCLAIMER);
-
-    // Fill in Windows 10 Mobile product ID or remove Windows 10 Mobile support when
-    // missing
-    if let Some(mob_ver) = &appx_config.phone_product_id {
-        manifest = manifest
-            .replace("$win10mob-begin$", "")
-            .replace("$win10mob-end$", "")
-            .replace("$appx-identity-phoneproductid$", mob_ver);
-    } else {
-        while let Some(begin) = manifest.find("$win10mob-begin$") {
-            if let Some(end) = manifest
-                .find("$win10mob-end$")
-                .map(|pos| pos + "$win10mob-end$".len())
-            {
-                if end < begin {
-                    return Err("Unmatched '$win10mobile_end$' placeholder".into());
-                }
-                manifest.replace_range(begin..end, "");
+    let mut variables = vec![
+        Variable {
+            raw: true,
+            ..Variable::new("generated-content-disclaimer", "", XML_DISCLAIMER)
+        },
+        Variable::new(
+            "appx-identity-name",
+            PACKAGE_IDENTITY_NAME_KEY,
+            &appx_config.identity_name,
+        ),
+        Variable::new(
+            "appx-identity-arch",
+            "build.target",
+            cargo_pkg_config.arch.display(),
+        ),
+        Variable::new(
+            "appx-identity-publisher",
+            PACKAGE_IDENTITY_PUBLISHER_KEY,
+            &appx_config.identity_publisher,
+        ),
+        Variable::new(
+            "appx-identity-version",
+            PACKAGE_IDENTITY_VERSION_KEY,
+            &appx_config.identity_version,
+        ),
+        Variable::new(
+            "appx-prop-displayname",
+            PACKAGE_DISPLAY_NAME_KEY,
+            &appx_config.display_name,
+        ),
+        Variable::new(
+            "appx-prop-publisherdisplayname",
+            PACKAGE_PUBLISHER_DISPLAY_NAME_KEY,
+            &appx_config.publisher_display_name,
+        ),
+        Variable::new(
+            "appx-application-executable",
+            "package.name",
+            &cargo_pkg_config.executable,
+        ),
+        Variable::new(
+            "appx-application-displayname",
+            PACKAGE_VISUAL_DISPLAY_NAME_KEY,
+            &appx_config.app_display_name,
+        ),
+        Variable::new(
+            "appx-application-description",
+            PACKAGE_VISUAL_DESCRIPTION_KEY,
+            &appx_config.app_description,
+        ),
+    ];
+    let capabilities_element =
+        capabilities::render(&appx_config.capabilities, &appx_config.device_capabilities);
+    let restricted = appx_config
+        .capabilities
+        .iter()
+        .any(|capability| capabilities::is_restricted(capability));
+    if !capabilities_element.is_empty() {
+        check_placeholder(&template, "appx-capabilities", CAPABILITIES_KEY)?;
+    }
+    variables.push(Variable {
+        raw: true,
+        ..Variable::new("appx-capabilities", CAPABILITIES_KEY, &capabilities_element)
+    });
+    variables.push(Variable::new(
+        "appx-capabilities-restricted",
+        CAPABILITIES_KEY,
+        &restricted.to_string(),
+    ));
+    let default_target_device_families = [TargetDeviceFamily::default()];
+    let target_device_families = appx_config
+        .target_device_families
+        .as_deref()
+        .unwrap_or(&default_target_device_families);
+    if appx_config.target_device_families.is_some() {
+        check_placeholder(
+            &template,
+            "appx-target-device-families",
+            TARGET_DEVICE_FAMILIES_KEY,
+        )?;
+    }
+    variables.push(Variable {
+        raw: true,
+        ..Variable::new(
+            "appx-target-device-families",
+            TARGET_DEVICE_FAMILIES_KEY,
+            &target_device_families
+                .iter()
+                .map(|family| {
+                    format!(
+                        "\n    <TargetDeviceFamily Name=\"{}\" MinVersion=\"{}\" \
+                         MaxVersionTested=\"{}\" />",
+                        escape_attribute(&family.name),
+                        family.min_version,
+                        family.max_version_tested
+                    )
+                })
+                .collect::<String>(),
+        )
+    });
+    let default_package_dependencies = PackageDependency::preset("vclibs", cargo_pkg_config.debug)
+        .into_iter()
+        .collect();
+    let package_dependencies = appx_config
+        .package_dependencies
+        .as_ref()
+        .unwrap_or(&default_package_dependencies);
+    if appx_config.package_dependencies.is_some() {
+        check_placeholder(
+            &template,
+            "appx-package-dependencies",
+            PACKAGE_DEPENDENCIES_KEY,
+        )?;
+    }
+    variables.push(Variable {
+        raw: true,
+        ..Variable::new(
+            "appx-package-dependencies",
+            PACKAGE_DEPENDENCIES_KEY,
+            &package_dependencies
+                .iter()
+                .map(|dependency| {
+                    format!(
+                        "\n    <PackageDependency Name=\"{}\" MinVersion=\"{}\" \
+                         Publisher=\"{}\" />",
+                        escape_attribute(&dependency.name),
+                        dependency.min_version,
+                        escape_attribute(&dependency.publisher)
+                    )
+                })
+                .collect::<String>(),
+        )
+    });
+    if appx_config.phone_publisher_id.is_some() {
+        check_placeholder(
+            &template,
+            "appx-identity-phonepublisherid",
+            PACKAGE_PHONE_PUBLISHER_ID_KEY,
+        )?;
+    }
+    variables.push(Variable::new(
+        "appx-identity-phonepublisherid",
+        PACKAGE_PHONE_PUBLISHER_ID_KEY,
+        appx_config
+            .phone_publisher_id
+            .as_deref()
+            .unwrap_or(PACKAGE_PHONE_PUBLISHER_ID_DEFAULT),
+    ));
+    if let Some(phone_product_id) = &appx_config.phone_product_id {
+        variables.push(Variable::new(
+            "appx-identity-phoneproductid",
+            PACKAGE_PHONE_ID_KEY,
+            phone_product_id,
+        ));
+    }
+    variables.extend(appx_config.variables.iter().cloned());
+    template::check_names(&variables)?;
+
+    // Templates created by earlier versions delimit Windows 10 Mobile support with
+    // dedicated markers
+    let template = template
+        .replace(
+            "$win10mob-begin$",
+            &format!("$if:{}$", PACKAGE_PHONE_ID_KEY),
+        )
+        .replace("$win10mob-end$", "$endif$");
+
+    let expand = |variables: &[Variable]| -> Result<String, Box<dyn Error>> {
+        let manifest = template::select(&template, variables)?;
+        template::substitute(&manifest, variables, |value, output| {
+            // Placeholders following an unclosed '<' are part of a tag, i.e. an
+            // attribute value
+            if output.rfind('<') > output.rfind('>') {
+                escape_attribute(value)
             } else {
-                return Err("Unmatched '$win10mobile_begin$' placeholder".into());
+                escape_text(value)
             }
+        })
+    };
+    let result = expand(&variables)
+        .map_err(|e| format!("Invalid templates/AppxManifest.xml file: {}", e))?;
+
+    // Make sure the result is well-formed. If it isn't, blame the first value without
+    // which the manifest turns out well-formed, or the template otherwise.
+    if let Err(e) = roxmltree::Document::parse(&result) {
+        let culprit = variables.iter().find(|culprit| {
+            let others = variables
+                .iter()
+                .map(|variable| Variable {
+                    value: if variable.key == culprit.key {
+                        String::new()
+                    } else {
+                        variable.value.clone()
+                    },
+                    ..variable.clone()
+                })
+                .collect::<Vec<_>>();
+            expand(&others).is_ok_and(|manifest| roxmltree::Document::parse(&manifest).is_ok())
+        });
+        return Err(match culprit {
+            Some(culprit) => format!("Invalid '{}' key: {}", culprit.key, e),
+            None => format!("Invalid templates/AppxManifest.xml file: {}", e),
         }
-    }
-    // Sanity check
-    if manifest
-        .find("$win10mob-begin$")
-        .or_else(|| manifest.find("$win10mob-end$"))
-        .or_else(|| manifest.find("$appx-identity-phoneproductid$"))
-        .is_some()
-    {
-        return Err("Failed to substitute all Windows 10 Mobile placeholders".into());
+        .into());
     }
 
-    // Replace Package Identity Name
-    manifest = manifest.replace("$appx-identity-name$", &appx_config.identity_name);
-
-    // Replace Package Identity Architecture
-    manifest = manifest.replace("$appx-identity-arch$", cargo_pkg_config.arch.display());
-
-    // Replace Package Identity Publisher
-    manifest = manifest.replace("$appx-identity-publisher$", &appx_config.identity_publisher);
-
-    // Replace Package Identity Version
-    manifest = manifest.replace("$appx-identity-version$", &appx_config.identity_version);
-
-    // Replace Package Property Display Name
-    manifest = manifest.replace("$appx-prop-displayname$", &appx_config.display_name);
-
-    // Replace Package Property Publisher Display Name
-    manifest = manifest.replace(
-        "$appx-prop-publisherdisplayname$",
-        &appx_config.publisher_display_name,
-    );
-
-    // Replace Package Application Executable
-    manifest = manifest.replace(
-        "$appx-application-executable$",
-        &cargo_pkg_config.executable,
-    );
+    Ok(result)
+}
 
-    // Replace application display name
-    manifest = manifest.replace(
-        "$appx-application-displayname$",
-        &appx_config.app_display_name,
-    );
+/// Fails if `template` lacks the placeholder of the built-in variable `name`, which is
+/// required to reflect the metadata `key`. Templates created by earlier versions lack the
+/// placeholders of later additions.
+///
+fn check_placeholder(template: &str, name: &str, key: &str) -> Result<(), Box<dyn Error>> {
+    if template.contains(&format!("${}$", name)) {
+        Ok(())
+    } else {
+        Err(format!(
+            "Invalid templates/AppxManifest.xml file: Missing '${}$' placeholder required \
+             by the '{}' key",
+            name, key
+        )
+        .into())
+    }
+}
 
-    // Replace application description
-    manifest = manifest.replace(
-        "$appx-application-description$",
-        &appx_config.app_description,
-    );
+fn escape_text(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
 
-    Ok(manifest)
+fn escape_attribute(value: &str) -> String {
+    // Whitespace other than spaces would be normalized away unless escaped
+    escape_text(value)
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+        .replace('\t', "&#x9;")
+        .replace('\n', "&#xA;")
+        .replace('\r', "&#xD;")
 }
 
 /// Generates a mapping file from the template, stripping comments and empty lines,
@@ -379,68 +897,101 @@ fn generate_appx_manifest(
 ///
 fn generate_mapping_file(
     template: String,
+    appx_config: &AppxConfig,
     cargo_config: &CargoConfig,
     cargo_pkg_config: &CargoPkgConfig,
 ) -> Result<String, Box<dyn Error>> {
+    let mut variables = vec![
+        Variable::new(
+            "target_dir",
+            "build.target-dir",
+            &cargo_config.target_dir.display().to_string(),
+        ),
+        Variable::new(
+            "cargo_package_root",
+            "package.root",
+            &cargo_config.package_root.display().to_string(),
+        ),
+        Variable::new("executable", "package.name", &cargo_pkg_config.executable),
+    ];
+    variables.extend(appx_config.variables.iter().cloned());
+    template::check_names(&variables)?;
+
+    // Comments document the placeholders, so they are stripped prior to expansion
+    let template = template
+        .lines()
+        .filter(|line| !line.starts_with(';'))
+        .collect::<Vec<_>>()
+        .join("\n");
+    let template = template::select(&template, &variables)
+        .map_err(|e| format!("Invalid templates/FileMapping.ini file: {}", e))?;
+    let verbatim = |value: &str, _: &str| value.to_owned();
+
     let mut output = Vec::<String>::new();
     for line in template
         .lines()
         .filter(|line| !line.is_empty() && !line.starts_with(';'))
         .chain(once(""))
     {
+        let expand = |text: &str, variables: &[Variable]| {
+            template::substitute(text, variables, verbatim)
+                .map_err(|e| format!("Invalid templates/FileMapping.ini file: {}", e))
+        };
         if let Some((lhs, rhs)) = parse_mapping(line) {
-            // Expand left-hand side
-            let lhs = lhs.replace(
-                "$target_dir$",
-                &cargo_config.target_dir.display().to_string(),
-            );
-            let lhs = lhs.replace(
-                "$cargo_package_root$",
-                &cargo_config.package_root.display().to_string(),
-            );
-            let lhs = lhs.replace("$executable$", &cargo_pkg_config.executable);
+            // Normalize path separators of the template to the local filesystem prior to
+            // expanding the left-hand side, so that expanded paths are left untouched
+            let lhs = lhs.replace(['\\', '/'], MAIN_SEPARATOR_STR);
+            let lhs = expand(&lhs, &variables)?;
 
             // Extract file name from left-hand side
             let file = PathBuf::from(&lhs);
-            let file_name = file.file_name().unwrap().to_string_lossy();
+            let file_name = file
+                .file_name()
+                .ok_or(format!("Mapping file entry '{}' doesn't name a file", lhs))?
+                .to_string_lossy();
 
-            // Expand right-hand side
-            let rhs = rhs.replace("$file_name$", &file_name);
+            // Expand right-hand side; package paths always use backslashes
+            let mut rhs_variables = variables.clone();
+            rhs_variables.push(Variable::new("file_name", "", &file_name));
+            let rhs = expand(&rhs, &rhs_variables)?.replace('/', "\\");
 
             output.push(format!("\"{}\" \"{}\"", lhs, rhs));
         } else {
-            output.push(line.to_owned())
+            output.push(expand(line, &variables)?)
         }
     }
 
     Ok(output.join("\n"))
 }
 
-fn parse_mapping(line: &str) -> Option<(String, String)> {
-    // The shortest valid line is `"a" "b"`, i.e. 7 characters long
-    if line.len() < 7 || !line.starts_with('\"') || !line.ends_with('\"') {
-        return None;
-    }
-    // Strip first and last quotation mark (")
-    let line = &line[1..line.len() - 1];
-
-    let end = line.find('\"');
-    let begin = line.rfind('\"');
-    if let (Some(end), Some(begin)) = (end, begin) {
-        if end != begin {
-            Some((line[..end].to_owned(), line[begin + 1..].to_owned()))
-        } else {
-            None
-        }
-    } else {
-        None
-    }
-}
-
 /// Constants used by the build system
 ///
 const TEMPLATES_DIR: &str = "templates";
 
+/// Keys of `[package.metadata.appxmanifest]` listing the capabilities of the application.
+const CAPABILITIES_KEY: &str = "capabilities";
+const DEVICE_CAPABILITIES_KEY: &str = "device-capabilities";
+
+/// Key of `[package.metadata.appxmanifest]` listing the target device families.
+const TARGET_DEVICE_FAMILIES_KEY: &str = "target-device-families";
+
+/// Key of `[package.metadata.appxmanifest]` listing the package dependencies, and the
+/// presets its entries can name.
+const PACKAGE_DEPENDENCIES_KEY: &str = "package-dependencies";
+const PACKAGE_DEPENDENCY_PRESETS: &[&str] = &[
+    "vclibs",
+    "vclibs-debug",
+    "microsoft-ui-xaml-2.7",
+    "microsoft-ui-xaml-2.8",
+];
+
+/// Publisher of the framework packages provided by Microsoft.
+const MICROSOFT_PUBLISHER: &str =
+    "CN=Microsoft Corporation, O=Microsoft Corporation, L=Redmond, S=Washington, C=US";
+
+/// Table of `[package.metadata.appxmanifest]` holding user-defined template variables.
+const TEMPLATE_VARS_KEY: &str = "vars";
+
 const XML_DISCLAIMER: &str = r#"
   <!--
     THIS PACKAGE MANIFEST FILE IS GENERATED BY THE BUILD PROCESS.
@@ -0,0 +1,209 @@
+//! Template engine expanding *templates/AppxManifest.xml* and *templates/FileMapping.ini*.
+//!
+//! Placeholders are enclosed in dollar signs:
+//!
+//! * `$name$` expands to the value of the variable `name`.
+//! * `$if:condition$` ... `$endif$` keeps its contents only if `condition` holds. An
+//!   optional `$else$` introduces contents kept otherwise. Blocks can be nested.
+//! * `$$` expands to a single dollar sign.
+//!
+//! A condition is either the name of a variable, holding if the variable is set to a
+//! value other than an empty string or `false`, or `feature:<name>`, holding if the cargo
+//! feature `<name>` is enabled. A leading `!` negates the condition.
+//!
+//! Dollar signs not starting a placeholder are copied verbatim. Placeholders naming an
+//! unknown variable fail the expansion.
+//!
+use std::{env, error::Error};
+
+/// A value substituted into a template, along with the Cargo.toml key it originates from.
+///
+#[derive(Clone, Debug)]
+pub struct Variable {
+    pub name: String,
+    pub key: String,
+    pub value: String,
+    /// Whether `value` is inserted as is, rather than passed through the escape function.
+    pub raw: bool,
+}
+
+impl Variable {
+    pub fn new(name: &str, key: &str, value: &str) -> Self {
+        Variable {
+            name: name.to_owned(),
+            key: key.to_owned(),
+            value: value.to_owned(),
+            raw: false,
+        }
+    }
+}
+
+/// Returns whether `name` is valid as the name of a user-defined variable.
+///
+pub fn is_valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        && name != "else"
+        && name != "endif"
+}
+
+/// Fails if two of `variables` share a name, naming the keys they originate from.
+///
+pub fn check_names(variables: &[Variable]) -> Result<(), Box<dyn Error>> {
+    for (index, variable) in variables.iter().enumerate() {
+        if let Some(other) = variables[index + 1..]
+            .iter()
+            .find(|other| other.name == variable.name)
+        {
+            return Err(format!(
+                "Variable '{}' is defined by both '{}' and '{}'",
+                variable.name, variable.key, other.key
+            )
+            .into());
+        }
+    }
+
+    Ok(())
+}
+
+/// Resolves all conditional blocks in `template`, leaving other placeholders in place.
+///
+pub fn select(template: &str, variables: &[Variable]) -> Result<String, Box<dyn Error>> {
+    // Each open block records its placeholder, whether the enclosing contents are kept,
+    // whether its condition holds, and whether `$else$` was seen
+    let mut blocks = Vec::<(&str, bool, bool, bool)>::new();
+    let mut output = String::with_capacity(template.len());
+    for token in tokenize(template) {
+        let active = blocks
+            .last()
+            .is_none_or(|&(_, outer, holds, in_else)| outer && holds != in_else);
+        match token {
+            Token::Placeholder(name) if name.starts_with("if:") => {
+                let holds = holds(&name["if:".len()..], variables)?;
+                blocks.push((name, active, holds, false));
+            }
+            Token::Placeholder("else") => match blocks.last_mut() {
+                Some((_, _, _, in_else)) if !*in_else => *in_else = true,
+                _ => return Err("Unmatched '$else$' placeholder".into()),
+            },
+            Token::Placeholder("endif") => {
+                if blocks.pop().is_none() {
+                    return Err("Unmatched '$endif$' placeholder".into());
+                }
+            }
+            _ if !active => {}
+            Token::Text(text) => output.push_str(text),
+            Token::Placeholder(name) => {
+                output.push('$');
+                output.push_str(name);
+                output.push('$');
+            }
+            Token::Dollar => output.push_str("$$"),
+        }
+    }
+    if let Some((name, ..)) = blocks.last() {
+        return Err(format!("Unmatched '${}$' placeholder", name).into());
+    }
+
+    Ok(output)
+}
+
+/// Replaces all variables in `template`. Values are passed through `escape` along with
+/// the output preceding the placeholder, unless they are raw.
+///
+pub fn substitute<F>(
+    template: &str,
+    variables: &[Variable],
+    escape: F,
+) -> Result<String, Box<dyn Error>>
+where
+    F: Fn(&str, &str) -> String,
+{
+    let mut output = String::with_capacity(template.len());
+    for token in tokenize(template) {
+        match token {
+            Token::Text(text) => output.push_str(text),
+            Token::Dollar => output.push('$'),
+            Token::Placeholder(name) => {
+                let variable = variables
+                    .iter()
+                    .find(|variable| variable.name == name)
+                    .ok_or(format!("Unknown placeholder '${}$'", name))?;
+                if variable.raw {
+                    output.push_str(&variable.value);
+                } else {
+                    let value = escape(&variable.value, &output);
+                    output.push_str(&value);
+                }
+            }
+        }
+    }
+
+    Ok(output)
+}
+
+/// Evaluates the condition of an `$if:...$` placeholder.
+///
+fn holds(condition: &str, variables: &[Variable]) -> Result<bool, Box<dyn Error>> {
+    let (negated, condition) = match condition.strip_prefix('!') {
+        Some(condition) => (true, condition),
+        None => (false, condition),
+    };
+    let holds = if let Some(feature) = condition.strip_prefix("feature:") {
+        if feature.is_empty() {
+            return Err("Missing feature name in '$if:feature:$' placeholder".into());
+        }
+        let feature = feature.to_uppercase().replace('-', "_");
+        env::var_os(format!("CARGO_FEATURE_{}", feature)).is_some()
+    } else if condition.is_empty() {
+        return Err("Missing condition in '$if:$' placeholder".into());
+    } else {
+        variables
+            .iter()
+            .find(|variable| variable.name == condition)
+            .is_some_and(|variable| !variable.value.is_empty() && variable.value != "false")
+    };
+
+    Ok(holds != negated)
+}
+
+enum Token<'a> {
+    Text(&'a str),
+    /// A placeholder, without the enclosing dollar signs
+    Placeholder(&'a str),
+    /// An escaped dollar sign (`$$`)
+    Dollar,
+}
+
+fn tokenize(template: &str) -> Vec<Token<'_>> {
+    let mut tokens = Vec::new();
+    let mut rest = template;
+    while let Some(pos) = rest.find('$') {
+        if pos > 0 {
+            tokens.push(Token::Text(&rest[..pos]));
+        }
+        let after = &rest[pos + 1..];
+        if let Some(after) = after.strip_prefix('$') {
+            tokens.push(Token::Dollar);
+            rest = after;
+            continue;
+        }
+        let len = after
+            .find(|c: char| !(c.is_ascii_alphanumeric() || "-_:!".contains(c)))
+            .unwrap_or(after.len());
+        if len > 0 && after[len..].starts_with('$') {
+            tokens.push(Token::Placeholder(&after[..len]));
+            rest = &after[len + 1..];
+        } else {
+            tokens.push(Token::Text(&rest[pos..=pos]));
+            rest = after;
+        }
+    }
+    if !rest.is_empty() {
+        tokens.push(Token::Text(rest));
+    }
+
+    tokens
+}
@@ -0,0 +1,61 @@
+#![windows_subsystem = "windows"]
+
+use windows::{
+    core::{implement, Result, HSTRING},
+    ApplicationModel::Core::{
+        CoreApplication, CoreApplicationView, IFrameworkView, IFrameworkViewSource,
+        IFrameworkViewSource_Impl, IFrameworkView_Impl,
+    },
+    Win32::System::Com::{CoInitializeEx, COINIT_MULTITHREADED},
+    UI::Core::{CoreProcessEventsOption, CoreWindow},
+};
+
+#[implement(IFrameworkViewSource)]
+struct MyViewSource;
+
+#[allow(non_snake_case)]
+impl IFrameworkViewSource_Impl for MyViewSource {
+    fn CreateView(&self) -> Result<IFrameworkView> {
+        Ok(MyView.into())
+    }
+}
+
+#[implement(IFrameworkView)]
+struct MyView;
+
+#[allow(non_snake_case)]
+impl IFrameworkView_Impl for MyView {
+    fn Initialize(&self, _: &Option<CoreApplicationView>) -> Result<()> {
+        Ok(())
+    }
+
+    fn SetWindow(&self, _: &Option<CoreWindow>) -> Result<()> {
+        Ok(())
+    }
+
+    fn Load(&self, _: &HSTRING) -> Result<()> {
+        Ok(())
+    }
+
+    fn Run(&self) -> Result<()> {
+        let window = CoreWindow::GetForCurrentThread()?;
+        window.Activate()?;
+
+        // Dispatch events until the window is closed
+        window
+            .Dispatcher()?
+            .ProcessEvents(CoreProcessEventsOption::ProcessUntilQuit)
+    }
+
+    fn Uninitialize(&self) -> Result<()> {
+        Ok(())
+    }
+}
+
+fn main() -> Result<()> {
+    unsafe {
+        CoInitializeEx(None, COINIT_MULTITHREADED)?;
+    }
+    let view_source: IFrameworkViewSource = MyViewSource.into();
+    CoreApplication::Run(&view_source)
+}
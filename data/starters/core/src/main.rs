@@ -0,0 +1,62 @@
+#![windows_subsystem = "windows"]
+
+use bindings::*;
+use windows::*;
+
+use bindings::{
+    Windows::ApplicationModel::Core::{
+        CoreApplication, CoreApplicationView, IFrameworkView, IFrameworkViewSource,
+    },
+    Windows::Win32::System::Com::*,
+    Windows::UI::Core::{CoreProcessEventsOption, CoreWindow},
+};
+
+#[implement(Windows::ApplicationModel::Core::IFrameworkViewSource)]
+struct MyViewSource();
+
+#[allow(non_snake_case)]
+impl MyViewSource {
+    fn CreateView(&self) -> Result<IFrameworkView> {
+        Ok(MyView().into())
+    }
+}
+
+#[implement(Windows::ApplicationModel::Core::IFrameworkView)]
+struct MyView();
+
+#[allow(non_snake_case)]
+impl MyView {
+    fn Initialize(&self, _: &Option<CoreApplicationView>) -> Result<()> {
+        Ok(())
+    }
+
+    fn SetWindow(&self, _: &Option<CoreWindow>) -> Result<()> {
+        Ok(())
+    }
+
+    fn Load(&self, _: &HSTRING) -> Result<()> {
+        Ok(())
+    }
+
+    fn Run(&self) -> Result<()> {
+        let window = CoreWindow::GetForCurrentThread()?;
+        window.Activate()?;
+
+        // Dispatch events until the window is closed
+        window
+            .Dispatcher()?
+            .ProcessEvents(CoreProcessEventsOption::ProcessUntilQuit)
+    }
+
+    fn Uninitialize(&self) -> Result<()> {
+        Ok(())
+    }
+}
+
+fn main() -> Result<()> {
+    unsafe {
+        CoInitializeEx(std::ptr::null_mut(), COINIT_MULTITHREADED)?;
+    }
+    let view_source: IFrameworkViewSource = MyViewSource().into();
+    CoreApplication::Run(view_source)
+}
@@ -0,0 +1,162 @@
+#![windows_subsystem = "windows"]
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use bindings::*;
+use windows::*;
+
+use bindings::{
+    Windows::ApplicationModel::Core::{
+        CoreApplication, CoreApplicationView, IFrameworkView, IFrameworkViewSource,
+    },
+    Windows::Foundation::TypedEventHandler,
+    Windows::Win32::Graphics::Direct3D11::*,
+    Windows::Win32::Graphics::Dxgi::*,
+    Windows::Win32::System::Com::*,
+    Windows::UI::Core::{CoreProcessEventsOption, CoreWindow, CoreWindowEventArgs},
+};
+
+#[implement(Windows::ApplicationModel::Core::IFrameworkViewSource)]
+struct MyViewSource();
+
+#[allow(non_snake_case)]
+impl MyViewSource {
+    fn CreateView(&self) -> Result<IFrameworkView> {
+        Ok(MyView().into())
+    }
+}
+
+#[implement(Windows::ApplicationModel::Core::IFrameworkView)]
+struct MyView();
+
+#[allow(non_snake_case)]
+impl MyView {
+    fn Initialize(&self, _: &Option<CoreApplicationView>) -> Result<()> {
+        Ok(())
+    }
+
+    fn SetWindow(&self, _: &Option<CoreWindow>) -> Result<()> {
+        Ok(())
+    }
+
+    fn Load(&self, _: &HSTRING) -> Result<()> {
+        Ok(())
+    }
+
+    fn Run(&self) -> Result<()> {
+        let window = CoreWindow::GetForCurrentThread()?;
+        let closed = Arc::new(AtomicBool::new(false));
+        let handler_closed = closed.clone();
+        window.Closed(TypedEventHandler::<CoreWindow, CoreWindowEventArgs>::new(
+            move |_, _| {
+                handler_closed.store(true, Ordering::SeqCst);
+                Ok(())
+            },
+        ))?;
+        window.Activate()?;
+
+        let renderer = Renderer::new(&window)?;
+        let dispatcher = window.Dispatcher()?;
+        let mut frame = 0u32;
+        while !closed.load(Ordering::SeqCst) {
+            dispatcher.ProcessEvents(CoreProcessEventsOption::ProcessAllIfPresent)?;
+            renderer.render(frame)?;
+            frame = frame.wrapping_add(1);
+        }
+
+        Ok(())
+    }
+
+    fn Uninitialize(&self) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Direct3D device and swap chain presenting to the application window.
+struct Renderer {
+    context: ID3D11DeviceContext,
+    swap_chain: IDXGISwapChain1,
+    target: ID3D11RenderTargetView,
+}
+
+impl Renderer {
+    fn new(window: &CoreWindow) -> Result<Self> {
+        unsafe {
+            let mut device = None;
+            let mut context = None;
+            D3D11CreateDevice(
+                None,
+                D3D_DRIVER_TYPE::D3D_DRIVER_TYPE_HARDWARE,
+                None,
+                D3D11_CREATE_DEVICE_FLAG::D3D11_CREATE_DEVICE_BGRA_SUPPORT,
+                std::ptr::null(),
+                0,
+                D3D11_SDK_VERSION,
+                &mut device,
+                std::ptr::null_mut(),
+                &mut context,
+            )
+            .ok()?;
+            let device: ID3D11Device = device.unwrap();
+            let context = context.unwrap();
+
+            let factory: IDXGIFactory2 = CreateDXGIFactory2(0)?;
+            let desc = DXGI_SWAP_CHAIN_DESC1 {
+                Format: DXGI_FORMAT::DXGI_FORMAT_B8G8R8A8_UNORM,
+                SampleDesc: DXGI_SAMPLE_DESC {
+                    Count: 1,
+                    Quality: 0,
+                },
+                BufferUsage: DXGI_USAGE_RENDER_TARGET_OUTPUT,
+                BufferCount: 2,
+                SwapEffect: DXGI_SWAP_EFFECT::DXGI_SWAP_EFFECT_FLIP_SEQUENTIAL,
+                ..Default::default()
+            };
+            let mut swap_chain = None;
+            factory
+                .CreateSwapChainForCoreWindow(&device, window, &desc, None, &mut swap_chain)
+                .ok()?;
+            let swap_chain: IDXGISwapChain1 = swap_chain.unwrap();
+
+            let back_buffer: ID3D11Texture2D = swap_chain.GetBuffer(0)?;
+            let mut target = None;
+            device
+                .CreateRenderTargetView(&back_buffer, std::ptr::null(), &mut target)
+                .ok()?;
+
+            Ok(Self {
+                context,
+                swap_chain,
+                target: target.unwrap(),
+            })
+        }
+    }
+
+    /// Clears the back buffer to a color cycling over time, and presents it, waiting for
+    /// the next vertical blank.
+    fn render(&self, frame: u32) -> Result<()> {
+        let phase = (frame % 360) as f32 * std::f32::consts::PI / 180.0;
+        let color = [
+            0.5 + 0.5 * phase.sin(),
+            0.5 + 0.5 * (phase + 2.0).sin(),
+            0.5 + 0.5 * (phase + 4.0).sin(),
+            1.0,
+        ];
+        unsafe {
+            self.context
+                .OMSetRenderTargets(1, &Some(self.target.clone()), None);
+            self.context
+                .ClearRenderTargetView(&self.target, color.as_ptr());
+            self.swap_chain.Present(1, 0).ok()
+        }
+    }
+}
+
+fn main() -> Result<()> {
+    unsafe {
+        CoInitializeEx(std::ptr::null_mut(), COINIT_MULTITHREADED)?;
+    }
+    let view_source: IFrameworkViewSource = MyViewSource().into();
+    CoreApplication::Run(view_source)
+}
@@ -0,0 +1,634 @@
+//! Implements reading and writing of UWP application packages (.appx, .msix).
+//!
+//! A package is an [OPC](https://en.wikipedia.org/wiki/Open_Packaging_Conventions) ZIP
+//! archive with two additional footprint files:
+//!
+//! * *\[Content_Types\].xml*: Maps file extensions (or individual parts) to MIME types.
+//! * *AppxBlockMap.xml*: Lists every payload file, split into 64 KiB blocks, alongside
+//!   the hash (SHA-256 by default) of each block. When compressed, each block is deflated
+//!   independently, and the block map records the compressed size of every block.
+//!
+use std::{
+    collections::BTreeMap,
+    fmt, fs,
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use anyhow::{anyhow, bail, Context};
+use flate2::{Compress, Compression, FlushCompress, Status};
+use sha2::{Digest, Sha256, Sha384, Sha512};
+
+use crate::shared::parse_mapping;
+use crate::zip::{Entry, Method, ZipWriter};
+
+/// Size of the blocks file data is split into for the block map.
+pub(crate) const BLOCK_SIZE: usize = 64 * 1024;
+
+pub(crate) const APPX_MANIFEST_NAME: &str = "AppxManifest.xml";
+pub(crate) const APPX_BLOCK_MAP_NAME: &str = "AppxBlockMap.xml";
+pub(crate) const APPX_SIGNATURE_NAME: &str = "AppxSignature.p7x";
+pub(crate) const CONTENT_TYPES_NAME: &str = "[Content_Types].xml";
+
+const BLOCK_MAP_NAMESPACE: &str = "http://schemas.microsoft.com/appx/2010/blockmap";
+const CONTENT_TYPES_NAMESPACE: &str =
+    "http://schemas.openxmlformats.org/package/2006/content-types";
+
+const MANIFEST_CONTENT_TYPE: &str = "application/vnd.ms-appx.manifest+xml";
+pub(crate) const BLOCK_MAP_CONTENT_TYPE: &str = "application/vnd.ms-appx.blockmap+xml";
+const DEFAULT_CONTENT_TYPE: &str = "application/octet-stream";
+
+/// Content types for well-known file extensions. Extensions not listed here map to
+/// `DEFAULT_CONTENT_TYPE`.
+///
+const CONTENT_TYPES: &[(&str, &str)] = &[
+    ("appx", "application/vnd.ms-appx"),
+    ("bmp", "image/bmp"),
+    ("css", "text/css"),
+    ("dll", "application/x-msdownload"),
+    ("exe", "application/x-msdownload"),
+    ("gif", "image/gif"),
+    ("htm", "text/html"),
+    ("html", "text/html"),
+    ("ico", "image/vnd.microsoft.icon"),
+    ("jpeg", "image/jpeg"),
+    ("jpg", "image/jpeg"),
+    ("js", "application/javascript"),
+    ("json", "application/json"),
+    ("msix", "application/vnd.ms-appx"),
+    ("png", "image/png"),
+    ("svg", "image/svg+xml"),
+    ("txt", "text/plain"),
+    ("xml", "application/xml"),
+];
+
+/// The package format, controlling the file extensions of generated artifacts.
+///
+/// Both formats share the same binary layout. MSIX is the successor to APPX, and requires
+/// Windows 10, version 1709 or later.
+///
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Format {
+    Appx,
+    Msix,
+}
+
+impl Format {
+    pub(crate) fn package_extension(self) -> &'static str {
+        match self {
+            Format::Appx => "appx",
+            Format::Msix => "msix",
+        }
+    }
+
+    pub(crate) fn bundle_extension(self) -> &'static str {
+        match self {
+            Format::Appx => "appxbundle",
+            Format::Msix => "msixbundle",
+        }
+    }
+
+    pub(crate) fn upload_extension(self) -> &'static str {
+        match self {
+            Format::Appx => "appxupload",
+            Format::Msix => "msixupload",
+        }
+    }
+}
+
+impl FromStr for Format {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "appx" => Ok(Format::Appx),
+            "msix" => Ok(Format::Msix),
+            _ => Err(anyhow!(
+                "Unknown package format '{}' (expected appx or msix)",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Format {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.package_extension())
+    }
+}
+
+/// The hash algorithm used for the block map, and for signing.
+///
+/// A package must be signed using the same hash algorithm its block map was created with.
+///
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub(crate) enum HashAlgorithm {
+    #[default]
+    Sha256,
+    Sha384,
+    Sha512,
+}
+
+impl HashAlgorithm {
+    /// Returns the identifier of the algorithm in the `HashMethod` attribute of the block
+    /// map.
+    ///
+    pub(crate) fn block_map_uri(self) -> &'static str {
+        match self {
+            HashAlgorithm::Sha256 => "http://www.w3.org/2001/04/xmlenc#sha256",
+            HashAlgorithm::Sha384 => "http://www.w3.org/2001/04/xmldsig-more#sha384",
+            HashAlgorithm::Sha512 => "http://www.w3.org/2001/04/xmlenc#sha512",
+        }
+    }
+
+    pub(crate) fn from_block_map_uri(uri: &str) -> Option<Self> {
+        [
+            HashAlgorithm::Sha256,
+            HashAlgorithm::Sha384,
+            HashAlgorithm::Sha512,
+        ]
+        .iter()
+        .copied()
+        .find(|algorithm| algorithm.block_map_uri() == uri)
+    }
+
+    pub(crate) fn digest(self, data: &[u8]) -> Vec<u8> {
+        match self {
+            HashAlgorithm::Sha256 => Sha256::digest(data).to_vec(),
+            HashAlgorithm::Sha384 => Sha384::digest(data).to_vec(),
+            HashAlgorithm::Sha512 => Sha512::digest(data).to_vec(),
+        }
+    }
+}
+
+impl FromStr for HashAlgorithm {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().replace('-', "").as_str() {
+            "sha256" => Ok(HashAlgorithm::Sha256),
+            "sha384" => Ok(HashAlgorithm::Sha384),
+            "sha512" => Ok(HashAlgorithm::Sha512),
+            _ => Err(anyhow!(
+                "Unknown hash algorithm '{}' (expected sha256, sha384, or sha512)",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for HashAlgorithm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            HashAlgorithm::Sha256 => "sha256",
+            HashAlgorithm::Sha384 => "sha384",
+            HashAlgorithm::Sha512 => "sha512",
+        })
+    }
+}
+
+/// A file to be included in a package.
+///
+#[derive(Clone, Debug)]
+pub(crate) struct PackageFile {
+    /// Location of the file on disk.
+    pub(crate) source: PathBuf,
+    /// Package-relative path name, using backslashes as path separators.
+    pub(crate) name: String,
+}
+
+/// The `<Identity>` of a package, as declared in its manifest.
+///
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct PackageIdentity {
+    pub(crate) name: String,
+    pub(crate) publisher: String,
+    pub(crate) version: String,
+    pub(crate) arch: String,
+}
+
+impl PackageIdentity {
+    /// Parses the `<Package><Identity>` element of an application manifest.
+    ///
+    pub(crate) fn from_manifest(manifest: &str) -> anyhow::Result<Self> {
+        let manifest = manifest.trim_start_matches('\u{feff}');
+        let document = roxmltree::Document::parse(manifest).context("Parsing AppxManifest.xml")?;
+        let identity = document
+            .root_element()
+            .children()
+            .find(|node| node.has_tag_name("Identity"))
+            .ok_or_else(|| anyhow!("AppxManifest.xml is missing the <Identity> element"))?;
+
+        let attribute = |name: &str| -> anyhow::Result<String> {
+            identity
+                .attribute(name)
+                .map(str::to_owned)
+                .ok_or_else(|| anyhow!("<Identity> element is missing the '{}' attribute", name))
+        };
+
+        Ok(Self {
+            name: attribute("Name")?,
+            publisher: attribute("Publisher")?,
+            version: attribute("Version")?,
+            arch: identity
+                .attribute("ProcessorArchitecture")
+                .unwrap_or("neutral")
+                .to_owned(),
+        })
+    }
+
+    /// Returns the conventional package file name `<name>_<version>_<arch>.<extension>`.
+    ///
+    pub(crate) fn package_file_name(&self, format: Format) -> String {
+        format!(
+            "{}_{}_{}.{}",
+            self.name,
+            self.version,
+            self.arch,
+            format.package_extension()
+        )
+    }
+
+    /// Returns the conventional symbol archive file name `<name>_<version>_<arch>.appxsym`.
+    ///
+    pub(crate) fn symbol_file_name(&self) -> String {
+        format!("{}_{}_{}.appxsym", self.name, self.version, self.arch)
+    }
+}
+
+/// Reads a *FileMapping.ini* file as generated by the build system.
+///
+pub(crate) fn read_mapping_file(path: &Path) -> anyhow::Result<Vec<PackageFile>> {
+    let contents = fs::read_to_string(path)
+        .with_context(|| format!("Reading mapping file {}", path.display()))?;
+
+    let mut files = Vec::new();
+    let mut in_files_section = false;
+    for line in contents.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with(';') {
+            continue;
+        }
+        if line.starts_with('[') && line.ends_with(']') {
+            in_files_section = line.eq_ignore_ascii_case("[Files]");
+            continue;
+        }
+        if !in_files_section {
+            continue;
+        }
+
+        let (source, name) = parse_mapping(line)
+            .ok_or_else(|| anyhow!("Invalid line in mapping file {}: {}", path.display(), line))?;
+        files.push(PackageFile {
+            source: PathBuf::from(source),
+            name,
+        });
+    }
+
+    Ok(files)
+}
+
+/// Writes a package containing `files` to `path`. `files` must include the application
+/// manifest (*AppxManifest.xml*). The block map and content types are generated.
+///
+/// When `compress` is `false`, all files are stored uncompressed. Blocks are hashed using
+/// `hash_algorithm`.
+///
+pub(crate) fn write_package(
+    path: &Path,
+    files: &[PackageFile],
+    compress: bool,
+    hash_algorithm: HashAlgorithm,
+) -> anyhow::Result<()> {
+    validate_files(files)?;
+
+    let result = write_package_unchecked(path, files, compress, hash_algorithm);
+    if result.is_err() {
+        // Don't leave a truncated package behind
+        let _ = fs::remove_file(path);
+    }
+    result
+}
+
+fn write_package_unchecked(
+    path: &Path,
+    files: &[PackageFile],
+    compress: bool,
+    hash_algorithm: HashAlgorithm,
+) -> anyhow::Result<()> {
+    let output = File::create(path).with_context(|| format!("Creating {}", path.display()))?;
+    let mut zip = ZipWriter::new(BufWriter::new(output));
+    let mut block_map = BlockMap::new(hash_algorithm);
+
+    for file in files {
+        let data =
+            fs::read(&file.source).with_context(|| format!("Reading {}", file.source.display()))?;
+        let payload = Payload::new(&data, compress)?;
+        let entry = write_entry(&mut zip, &part_name(&file.name), &data, &payload)?;
+        block_map.add_file(&file.name, &data, entry.header_size, &payload);
+    }
+
+    // Footprint files go last, with the content types concluding the archive
+    let block_map = block_map.to_xml();
+    let payload = Payload::new(block_map.as_bytes(), compress)?;
+    write_entry(
+        &mut zip,
+        APPX_BLOCK_MAP_NAME,
+        block_map.as_bytes(),
+        &payload,
+    )?;
+
+    let content_types = content_types_xml(
+        files.iter().map(|file| file.name.as_str()),
+        &[
+            (APPX_MANIFEST_NAME, MANIFEST_CONTENT_TYPE),
+            (APPX_BLOCK_MAP_NAME, BLOCK_MAP_CONTENT_TYPE),
+        ],
+    );
+    let payload = Payload::new(content_types.as_bytes(), compress)?;
+    write_entry(
+        &mut zip,
+        CONTENT_TYPES_NAME,
+        content_types.as_bytes(),
+        &payload,
+    )?;
+
+    zip.finish()?;
+
+    Ok(())
+}
+
+/// Checks package contents for a manifest, reserved names, invalid paths, and duplicates.
+///
+fn validate_files(files: &[PackageFile]) -> anyhow::Result<()> {
+    let mut seen = BTreeMap::new();
+    for file in files {
+        let name = file.name.as_str();
+        if name.is_empty()
+            || name.starts_with('\\')
+            || name.starts_with('/')
+            || name.contains(':')
+            || name
+                .split(['\\', '/'])
+                .any(|part| part.is_empty() || part == "." || part == "..")
+        {
+            bail!("Invalid package-relative path name '{}'", name);
+        }
+        if [APPX_BLOCK_MAP_NAME, APPX_SIGNATURE_NAME, CONTENT_TYPES_NAME]
+            .iter()
+            .any(|reserved| reserved.eq_ignore_ascii_case(name))
+        {
+            bail!("'{}' is a reserved file name and cannot be packaged", name);
+        }
+        if let Some(previous) = seen.insert(name.replace('/', "\\").to_lowercase(), name) {
+            bail!(
+                "Duplicate package-relative path name '{}' (previously '{}')",
+                name,
+                previous
+            );
+        }
+    }
+    if !seen.contains_key(&APPX_MANIFEST_NAME.to_lowercase()) {
+        bail!("Package contents don't include {}", APPX_MANIFEST_NAME);
+    }
+
+    Ok(())
+}
+
+/// Writes `data`, encoded as `payload`, to the archive entry `part_name`.
+///
+pub(crate) fn write_entry<'z, W: Write>(
+    zip: &'z mut ZipWriter<W>,
+    part_name: &str,
+    data: &[u8],
+    payload: &Payload,
+) -> io::Result<&'z Entry> {
+    zip.write_entry(
+        part_name,
+        payload.method,
+        crc32fast::hash(data),
+        data.len() as u64,
+        &payload.data,
+    )
+}
+
+/// File data as stored in the archive, together with the compressed size of each block.
+///
+pub(crate) struct Payload {
+    method: Method,
+    data: Vec<u8>,
+    /// Compressed size of each block; empty when stored uncompressed.
+    block_sizes: Vec<u64>,
+}
+
+impl Payload {
+    pub(crate) fn new(data: &[u8], compress: bool) -> io::Result<Self> {
+        if !compress || data.is_empty() {
+            return Ok(Self {
+                method: Method::Stored,
+                data: data.to_owned(),
+                block_sizes: Vec::new(),
+            });
+        }
+
+        // Deflate every block independently so that each one ends on a byte boundary
+        let mut compressor = Compress::new(Compression::default(), false);
+        let mut compressed = Vec::with_capacity(data.len() / 2);
+        let mut block_sizes = Vec::new();
+        let block_count = data.len().div_ceil(BLOCK_SIZE);
+        for (index, block) in data.chunks(BLOCK_SIZE).enumerate() {
+            let flush = if index + 1 == block_count {
+                FlushCompress::Finish
+            } else {
+                FlushCompress::Full
+            };
+            let start = compressed.len();
+            deflate(&mut compressor, block, flush, &mut compressed)?;
+            block_sizes.push((compressed.len() - start) as u64);
+        }
+
+        Ok(Self {
+            method: Method::Deflated,
+            data: compressed,
+            block_sizes,
+        })
+    }
+}
+
+/// Runs `input` through `compressor` until all of it is consumed and `flush` has
+/// completed, appending the compressed output to `output`.
+///
+fn deflate(
+    compressor: &mut Compress,
+    input: &[u8],
+    flush: FlushCompress,
+    output: &mut Vec<u8>,
+) -> io::Result<()> {
+    let start = compressor.total_in();
+    loop {
+        let consumed = (compressor.total_in() - start) as usize;
+        output.reserve(input.len() - consumed + 1024);
+        let status = compressor
+            .compress_vec(&input[consumed..], output, flush)
+            .map_err(io::Error::other)?;
+        let consumed = (compressor.total_in() - start) as usize;
+
+        let done = match flush {
+            FlushCompress::Finish => status == Status::StreamEnd,
+            _ => consumed == input.len() && output.len() < output.capacity(),
+        };
+        if done {
+            return Ok(());
+        }
+    }
+}
+
+/// Accumulates the contents of *AppxBlockMap.xml*.
+///
+pub(crate) struct BlockMap {
+    hash_algorithm: HashAlgorithm,
+    files: String,
+}
+
+impl BlockMap {
+    pub(crate) fn new(hash_algorithm: HashAlgorithm) -> Self {
+        Self {
+            hash_algorithm,
+            files: String::new(),
+        }
+    }
+
+    /// Records the file `name` holding `data`, stored in the archive as `payload`.
+    ///
+    pub(crate) fn add_file(
+        &mut self,
+        name: &str,
+        data: &[u8],
+        header_size: u64,
+        payload: &Payload,
+    ) {
+        self.files.push_str(&format!(
+            "<File Name=\"{}\" Size=\"{}\" LfhSize=\"{}\">",
+            escape_xml(&name.replace('/', "\\")),
+            data.len(),
+            header_size
+        ));
+        for (index, block) in data.chunks(BLOCK_SIZE).enumerate() {
+            let hash = base64::encode(self.hash_algorithm.digest(block));
+            match payload.block_sizes.get(index) {
+                Some(size) => self
+                    .files
+                    .push_str(&format!("<Block Hash=\"{}\" Size=\"{}\"/>", hash, size)),
+                None => self.files.push_str(&format!("<Block Hash=\"{}\"/>", hash)),
+            }
+        }
+        self.files.push_str("</File>");
+    }
+
+    pub(crate) fn to_xml(&self) -> String {
+        format!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"no\"?>\r\n\
+             <BlockMap xmlns=\"{}\" HashMethod=\"{}\">{}</BlockMap>",
+            BLOCK_MAP_NAMESPACE,
+            self.hash_algorithm.block_map_uri(),
+            self.files
+        )
+    }
+}
+
+/// Generates *\[Content_Types\].xml* for the package-relative file names in `names`.
+/// Files listed in `overrides` are assigned the given content type, irrespective of their
+/// file extension.
+///
+pub(crate) fn content_types_xml<'a>(
+    names: impl Iterator<Item = &'a str>,
+    overrides: &[(&str, &str)],
+) -> String {
+    let mut defaults = BTreeMap::new();
+    let mut overrides: Vec<_> = overrides
+        .iter()
+        .map(|(name, content_type)| (format!("/{}", part_name(name)), *content_type))
+        .collect();
+    let overridden: Vec<_> = overrides.iter().map(|(part, _)| part.clone()).collect();
+
+    for name in names {
+        let part = format!("/{}", part_name(name));
+        if overridden
+            .iter()
+            .any(|known| known.eq_ignore_ascii_case(&part))
+        {
+            continue;
+        }
+        let file_name = name.rsplit(['\\', '/']).next().unwrap_or(name);
+        match file_name.rsplit_once('.') {
+            Some((stem, extension)) if !stem.is_empty() && !extension.is_empty() => {
+                let extension = extension.to_lowercase();
+                let content_type = CONTENT_TYPES
+                    .iter()
+                    .find(|(known, _)| *known == extension)
+                    .map(|(_, content_type)| *content_type)
+                    .unwrap_or(DEFAULT_CONTENT_TYPE);
+                defaults.insert(extension, content_type);
+            }
+            _ => overrides.push((part, DEFAULT_CONTENT_TYPE)),
+        }
+    }
+
+    let mut xml = format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\r\n<Types xmlns=\"{}\">",
+        CONTENT_TYPES_NAMESPACE
+    );
+    for (extension, content_type) in defaults {
+        xml.push_str(&format!(
+            "<Default Extension=\"{}\" ContentType=\"{}\"/>",
+            escape_xml(&extension),
+            content_type
+        ));
+    }
+    for (part, content_type) in overrides {
+        xml.push_str(&format!(
+            "<Override PartName=\"{}\" ContentType=\"{}\"/>",
+            escape_xml(&part),
+            content_type
+        ));
+    }
+    xml.push_str("</Types>");
+
+    xml
+}
+
+/// Converts a package-relative path name into an OPC part name as stored in the ZIP
+/// archive: Forward slashes separate path segments, and characters outside the set
+/// permitted by RFC 3986 are percent-encoded.
+///
+pub(crate) fn part_name(name: &str) -> String {
+    let mut part = String::with_capacity(name.len());
+    for byte in name.bytes() {
+        match byte {
+            b'\\' | b'/' => part.push('/'),
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' => part.push(byte as char),
+            b'-' | b'.' | b'_' | b'~' | b'!' | b'$' | b'&' | b'\'' | b'(' | b')' | b'*' | b'+'
+            | b',' | b';' | b'=' | b':' | b'@' => part.push(byte as char),
+            _ => part.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    part
+}
+
+/// Escapes text for use in XML element content or attribute values.
+///
+pub(crate) fn escape_xml(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
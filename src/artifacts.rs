@@ -0,0 +1,197 @@
+//! Locates the cargo package and the artifacts produced by building it.
+
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{anyhow, Context};
+use toml_edit as toml;
+
+use crate::shared::ARTIFACT_DIR_ENV;
+
+/// Returns the root directory of the cargo package containing the current working
+/// directory, i.e. the closest ancestor holding a *Cargo.toml* file.
+///
+pub(crate) fn find_package_root() -> anyhow::Result<PathBuf> {
+    let current_dir = env::current_dir().context("Determining the current directory")?;
+    current_dir
+        .ancestors()
+        .find(|dir| dir.join("Cargo.toml").is_file())
+        .map(Path::to_path_buf)
+        .ok_or_else(|| {
+            anyhow!(
+                "Could not find Cargo.toml in {} or any parent directory",
+                current_dir.display()
+            )
+        })
+}
+
+/// Returns the cargo target directory of the package at `package_root`, honoring the
+/// `CARGO_TARGET_DIR` environment variable and the `build.target-dir` configuration key.
+///
+pub(crate) fn target_dir(package_root: &Path) -> anyhow::Result<PathBuf> {
+    let config = BuildConfig::read(package_root)?;
+    resolve_target_dir(package_root, config.target_dir)
+}
+
+/// Returns the directory the build artifacts of the package at `package_root` are
+/// compiled to, i.e. *\<target dir\>/\<target triple\>/\<profile dir\>*, or the directory
+/// set through `CARGO_UWP_ARTIFACT_DIR`.
+///
+/// When `target` is `None` the target triple is read from the `build.target` key of the
+/// cargo configuration.
+///
+pub(crate) fn artifact_dir(
+    package_root: &Path,
+    target: Option<&str>,
+    profile: &str,
+) -> anyhow::Result<PathBuf> {
+    if let Some(dir) = env::var_os(ARTIFACT_DIR_ENV) {
+        return Ok(package_root.join(dir));
+    }
+
+    let config = BuildConfig::read(package_root)?;
+
+    let target = target.map(str::to_owned).or(config.target).ok_or_else(|| {
+        anyhow!("No target specified, and no default target configured in .cargo/config.toml")
+    })?;
+
+    let mut dir = resolve_target_dir(package_root, config.target_dir)?;
+    dir.push(target);
+    dir.push(profile_dir_name(profile));
+
+    Ok(dir)
+}
+
+/// Returns the artifact directories of all target triples the package at `package_root`
+/// has been built for with `profile`, or only the directory set through
+/// `CARGO_UWP_ARTIFACT_DIR`.
+///
+pub(crate) fn all_artifact_dirs(
+    package_root: &Path,
+    profile: &str,
+) -> anyhow::Result<Vec<PathBuf>> {
+    if let Some(dir) = env::var_os(ARTIFACT_DIR_ENV) {
+        return Ok(vec![package_root.join(dir)]);
+    }
+
+    let target_dir = target_dir(package_root)?;
+    let mut dirs = Vec::new();
+    for entry in fs::read_dir(&target_dir)
+        .with_context(|| format!("Reading target directory {}", target_dir.display()))?
+    {
+        let dir = entry?.path().join(profile_dir_name(profile));
+        if dir.is_dir() {
+            dirs.push(dir);
+        }
+    }
+    dirs.sort();
+
+    Ok(dirs)
+}
+
+/// Returns the value of `key` in the `[package.metadata.appxmanifest]` table of the
+/// *Cargo.toml* file of the package at `package_root`.
+///
+pub(crate) fn manifest_metadata(package_root: &Path, key: &str) -> anyhow::Result<String> {
+    let (path, document) = read_manifest(package_root)?;
+    document["package"]["metadata"]["appxmanifest"][key]
+        .as_str()
+        .map(str::to_owned)
+        .ok_or_else(|| {
+            anyhow!(
+                "Missing '{}' in the [package.metadata.appxmanifest] table of {}",
+                key,
+                path.display()
+            )
+        })
+}
+
+/// Returns the name of the cargo package at `package_root`.
+///
+pub(crate) fn package_name(package_root: &Path) -> anyhow::Result<String> {
+    let (path, document) = read_manifest(package_root)?;
+    document["package"]["name"]
+        .as_str()
+        .map(str::to_owned)
+        .ok_or_else(|| anyhow!("Missing package name in {}", path.display()))
+}
+
+fn read_manifest(package_root: &Path) -> anyhow::Result<(PathBuf, toml::Document)> {
+    let path = package_root.join("Cargo.toml");
+    let contents =
+        fs::read_to_string(&path).with_context(|| format!("Reading {}", path.display()))?;
+    let document = contents
+        .parse()
+        .with_context(|| format!("Parsing {}", path.display()))?;
+    Ok((path, document))
+}
+
+/// Resolves the target directory the way cargo does: A relative `CARGO_TARGET_DIR` is
+/// relative to the current directory, and takes precedence over the `configured`
+/// directory, which defaults to *target* in the package root.
+///
+fn resolve_target_dir(package_root: &Path, configured: Option<PathBuf>) -> anyhow::Result<PathBuf> {
+    match env::var_os("CARGO_TARGET_DIR").map(PathBuf::from) {
+        Some(dir) if dir.is_relative() => {
+            let current_dir = env::current_dir().context("Determining the current directory")?;
+            Ok(current_dir.join(dir))
+        }
+        Some(dir) => Ok(dir),
+        None => Ok(configured.unwrap_or_else(|| package_root.join("target"))),
+    }
+}
+
+/// Returns the name of the directory artifacts built with the cargo profile `profile`
+/// are placed in.
+///
+fn profile_dir_name(profile: &str) -> &str {
+    match profile {
+        "dev" | "test" => "debug",
+        "bench" => "release",
+        _ => profile,
+    }
+}
+
+/// The subset of the `[build]` table of the cargo configuration relevant to locating
+/// build artifacts.
+///
+#[derive(Default)]
+struct BuildConfig {
+    target: Option<String>,
+    target_dir: Option<PathBuf>,
+}
+
+impl BuildConfig {
+    /// Reads the build configuration the same way cargo does: Configuration files in
+    /// directories closer to `package_root` take precedence over those further up.
+    ///
+    fn read(package_root: &Path) -> anyhow::Result<Self> {
+        let mut config = BuildConfig::default();
+        for dir in package_root.ancestors() {
+            for file_name in &["config.toml", "config"] {
+                let file = dir.join(".cargo").join(file_name);
+                if !file.is_file() {
+                    continue;
+                }
+
+                let contents = fs::read_to_string(&file)
+                    .with_context(|| format!("Reading {}", file.display()))?;
+                let document: toml::Document = contents
+                    .parse()
+                    .with_context(|| format!("Parsing {}", file.display()))?;
+                let build = &document["build"];
+                if config.target.is_none() {
+                    config.target = build["target"].as_str().map(str::to_owned);
+                }
+                if config.target_dir.is_none() {
+                    // Relative paths are relative to the parent of the .cargo directory
+                    config.target_dir = build["target-dir"].as_str().map(|path| dir.join(path));
+                }
+            }
+        }
+
+        Ok(config)
+    }
+}
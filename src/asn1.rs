@@ -0,0 +1,431 @@
+//! Minimal DER encoding of ASN.1 structures, and handling of X.500 distinguished names
+//! as spelled out in package manifests (e.g. `CN=Contoso, O=Contoso Corporation, C=US`).
+//!
+
+use anyhow::{anyhow, Context};
+
+pub(crate) const TAG_BOOLEAN: u8 = 0x01;
+pub(crate) const TAG_INTEGER: u8 = 0x02;
+pub(crate) const TAG_BIT_STRING: u8 = 0x03;
+pub(crate) const TAG_OCTET_STRING: u8 = 0x04;
+pub(crate) const TAG_OBJECT_IDENTIFIER: u8 = 0x06;
+pub(crate) const TAG_UTF8_STRING: u8 = 0x0c;
+pub(crate) const TAG_PRINTABLE_STRING: u8 = 0x13;
+pub(crate) const TAG_IA5_STRING: u8 = 0x16;
+pub(crate) const TAG_SEQUENCE: u8 = 0x30;
+pub(crate) const TAG_SET: u8 = 0x31;
+pub(crate) const TAG_CONTEXT_0: u8 = 0xa0;
+pub(crate) const TAG_CONTEXT_3: u8 = 0xa3;
+
+/// Short names of distinguished name attributes, as used in package manifests.
+///
+const NAME_ATTRIBUTES: &[(&str, &str)] = &[
+    ("2.5.4.3", "CN"),
+    ("2.5.4.4", "SN"),
+    ("2.5.4.5", "SERIALNUMBER"),
+    ("2.5.4.6", "C"),
+    ("2.5.4.7", "L"),
+    ("2.5.4.8", "S"),
+    ("2.5.4.9", "STREET"),
+    ("2.5.4.10", "O"),
+    ("2.5.4.11", "OU"),
+    ("2.5.4.12", "T"),
+    ("2.5.4.42", "G"),
+    ("1.2.840.113549.1.9.1", "E"),
+    ("0.9.2342.19200300.100.1.25", "DC"),
+];
+
+/// Parses a distinguished name such as `CN=Contoso, O="Contoso, Inc.", C=US` into
+/// `(short name, value)` pairs. Returns `None` if `name` is malformed.
+///
+pub(crate) fn parse_distinguished_name(name: &str) -> Option<Vec<(String, String)>> {
+    let mut attributes = Vec::new();
+    let mut chars = name.chars().peekable();
+    loop {
+        let mut attribute_name = String::new();
+        loop {
+            match chars.next()? {
+                '=' => break,
+                c => attribute_name.push(c),
+            }
+        }
+        let attribute_name = attribute_name.trim().to_uppercase();
+        if attribute_name.is_empty() {
+            return None;
+        }
+
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+        let mut value = String::new();
+        if chars.next_if_eq(&'"').is_some() {
+            // Quoted values may contain separators, with quotes escaped by doubling them
+            loop {
+                match chars.next()? {
+                    '"' if chars.next_if_eq(&'"').is_some() => value.push('"'),
+                    '"' => break,
+                    c => value.push(c),
+                }
+            }
+            while chars.next_if(|c| c.is_whitespace()).is_some() {}
+        } else {
+            while let Some(c) = chars.next_if(|c| !matches!(c, ',' | ';' | '+')) {
+                value.push(c);
+            }
+            value = value.trim_end().to_owned();
+        }
+
+        let attribute_name = match attribute_name.as_str() {
+            "ST" => "S".to_owned(),
+            _ => attribute_name,
+        };
+        attributes.push((attribute_name, value));
+
+        match chars.next() {
+            None => return Some(attributes),
+            Some(',' | ';' | '+') => continue,
+            Some(_) => return None,
+        }
+    }
+}
+
+/// Formats `(short name, value)` pairs as a distinguished name, quoting values where
+/// necessary.
+///
+pub(crate) fn format_distinguished_name(attributes: &[(String, String)]) -> String {
+    attributes
+        .iter()
+        .map(|(name, value)| format!("{}={}", name, quote_attribute_value(value)))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Encodes the distinguished name `name` as an X.509 `Name`.
+///
+pub(crate) fn encode_distinguished_name(name: &str) -> anyhow::Result<Vec<u8>> {
+    let attributes = parse_distinguished_name(name)
+        .ok_or_else(|| anyhow!("'{}' is not a valid distinguished name", name))?;
+
+    // Distinguished names are spelled out most specific first, but encoded the other way
+    // around
+    let mut rdns = Vec::new();
+    for (short_name, value) in attributes.iter().rev() {
+        let attribute_oid = match short_name.strip_prefix("OID.") {
+            Some(oid) => oid,
+            None => attribute_oid(short_name).ok_or_else(|| {
+                anyhow!("Unknown attribute '{}' in distinguished name", short_name)
+            })?,
+        };
+        let attribute_oid = oid(attribute_oid)
+            .with_context(|| format!("Invalid attribute '{}' in distinguished name", short_name))?;
+        let tag = match short_name.as_str() {
+            "C" => TAG_PRINTABLE_STRING,
+            "E" | "DC" => TAG_IA5_STRING,
+            _ => TAG_UTF8_STRING,
+        };
+        rdns.push(der(
+            TAG_SET,
+            &sequence(&[attribute_oid, der(tag, value.as_bytes())]),
+        ));
+    }
+
+    Ok(sequence(&rdns))
+}
+
+/// Returns the short name of the distinguished name attribute identified by `oid`, or
+/// `OID.<oid>` for attributes that don't have one.
+///
+pub(crate) fn attribute_short_name(oid: &str) -> String {
+    NAME_ATTRIBUTES
+        .iter()
+        .find(|(known, _)| *known == oid)
+        .map(|(_, name)| (*name).to_owned())
+        .unwrap_or_else(|| format!("OID.{}", oid))
+}
+
+fn attribute_oid(short_name: &str) -> Option<&'static str> {
+    NAME_ATTRIBUTES
+        .iter()
+        .find(|(_, name)| *name == short_name)
+        .map(|(oid, _)| *oid)
+}
+
+fn quote_attribute_value(value: &str) -> String {
+    let needs_quotes = value.is_empty()
+        || value.starts_with(' ')
+        || value.ends_with(' ')
+        || value.contains([',', ';', '+', '=', '"', '<', '>', '#', '\n']);
+    if needs_quotes {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_owned()
+    }
+}
+
+/// Encodes a DER value with the given `tag`.
+///
+pub(crate) fn der(tag: u8, contents: &[u8]) -> Vec<u8> {
+    let mut encoded = vec![tag];
+    let length = contents.len();
+    if length < 0x80 {
+        encoded.push(length as u8);
+    } else {
+        let bytes = length.to_be_bytes();
+        let skip = bytes.iter().take_while(|&&byte| byte == 0).count();
+        encoded.push(0x80 | (bytes.len() - skip) as u8);
+        encoded.extend_from_slice(&bytes[skip..]);
+    }
+    encoded.extend_from_slice(contents);
+    encoded
+}
+
+pub(crate) fn sequence(items: &[Vec<u8>]) -> Vec<u8> {
+    der(TAG_SEQUENCE, &items.concat())
+}
+
+pub(crate) fn integer(value: u32) -> Vec<u8> {
+    unsigned_integer(&value.to_be_bytes())
+}
+
+/// Encodes the big-endian unsigned integer `bytes` as an INTEGER.
+///
+pub(crate) fn unsigned_integer(bytes: &[u8]) -> Vec<u8> {
+    let skip = bytes
+        .iter()
+        .take_while(|&&byte| byte == 0)
+        .count()
+        .min(bytes.len().saturating_sub(1));
+    let mut contents = Vec::with_capacity(bytes.len() - skip + 1);
+    if bytes.get(skip).is_some_and(|&byte| byte & 0x80 != 0) {
+        contents.push(0);
+    }
+    contents.extend_from_slice(&bytes[skip..]);
+    der(TAG_INTEGER, &contents)
+}
+
+pub(crate) fn boolean(value: bool) -> Vec<u8> {
+    der(TAG_BOOLEAN, &[if value { 0xff } else { 0x00 }])
+}
+
+/// Encodes `bytes` as a BIT STRING without unused bits.
+///
+pub(crate) fn bit_string(bytes: &[u8]) -> Vec<u8> {
+    der(TAG_BIT_STRING, &[&[0], bytes].concat())
+}
+
+/// Encodes the dotted object identifier `dotted` (e.g. `2.5.4.3`) as an OBJECT IDENTIFIER.
+///
+pub(crate) fn oid(dotted: &str) -> anyhow::Result<Vec<u8>> {
+    let invalid = || anyhow!("'{}' is not a valid object identifier", dotted);
+    let arcs = dotted
+        .split('.')
+        .map(|arc| {
+            if arc.is_empty() || !arc.bytes().all(|b| b.is_ascii_digit()) {
+                return None;
+            }
+            arc.parse::<u64>().ok()
+        })
+        .collect::<Option<Vec<_>>>()
+        .ok_or_else(invalid)?;
+    // The first two arcs are encoded together, and the second one is limited to 0-39
+    // unless the first one is 2
+    match arcs.as_slice() {
+        [first, second, ..] if (*first < 2 && *second < 40) || *first == 2 => {}
+        _ => return Err(invalid()),
+    }
+
+    let mut contents = Vec::new();
+    for (index, &arc) in arcs.iter().enumerate().skip(1) {
+        let arc = if index == 1 {
+            arcs[0]
+                .checked_mul(40)
+                .and_then(|first| first.checked_add(arc))
+                .ok_or_else(invalid)?
+        } else {
+            arc
+        };
+        let mut encoded = vec![(arc & 0x7f) as u8];
+        let mut rest = arc >> 7;
+        while rest > 0 {
+            encoded.push(0x80 | (rest & 0x7f) as u8);
+            rest >>= 7;
+        }
+        contents.extend(encoded.iter().rev());
+    }
+    Ok(der(TAG_OBJECT_IDENTIFIER, &contents))
+}
+
+pub(crate) fn algorithm_identifier(algorithm: &str) -> anyhow::Result<Vec<u8>> {
+    Ok(sequence(&[oid(algorithm)?, vec![0x05, 0x00]]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn attributes(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+        pairs
+            .iter()
+            .map(|(name, value)| ((*name).to_owned(), (*value).to_owned()))
+            .collect()
+    }
+
+    #[test]
+    fn parses_distinguished_names() {
+        assert_eq!(
+            parse_distinguished_name("CN=Contoso, O=Contoso Corporation, C=US"),
+            Some(attributes(&[
+                ("CN", "Contoso"),
+                ("O", "Contoso Corporation"),
+                ("C", "US")
+            ]))
+        );
+        // Separators and whitespace
+        assert_eq!(
+            parse_distinguished_name(" cn = Contoso ;st=WA+ L=Redmond "),
+            Some(attributes(&[
+                ("CN", "Contoso"),
+                ("S", "WA"),
+                ("L", "Redmond")
+            ]))
+        );
+    }
+
+    #[test]
+    fn parses_quoted_values() {
+        assert_eq!(
+            parse_distinguished_name("CN=\"Contoso, Inc.\", O=\" padded \""),
+            Some(attributes(&[("CN", "Contoso, Inc."), ("O", " padded ")]))
+        );
+        assert_eq!(
+            parse_distinguished_name("CN=\"Say \"\"hi\"\"; or not\""),
+            Some(attributes(&[("CN", "Say \"hi\"; or not")]))
+        );
+        assert_eq!(
+            parse_distinguished_name("CN=\"\""),
+            Some(attributes(&[("CN", "")]))
+        );
+    }
+
+    #[test]
+    fn unquoted_commas_separate_attributes() {
+        // Commas are only part of a value if it is quoted; otherwise the remainder must be
+        // another attribute
+        assert_eq!(parse_distinguished_name("O=Contoso, Inc."), None);
+        assert_eq!(
+            parse_distinguished_name("O=\"Contoso, Inc.\""),
+            Some(attributes(&[("O", "Contoso, Inc.")]))
+        );
+        assert_eq!(
+            parse_distinguished_name("O=Contoso, Inc.=x"),
+            Some(attributes(&[("O", "Contoso"), ("INC.", "x")]))
+        );
+    }
+
+    #[test]
+    fn parses_oid_attributes() {
+        assert_eq!(
+            parse_distinguished_name("CN=Contoso, OID.1.3.6.1.4.1.311.60.2.1.3=US"),
+            Some(attributes(&[
+                ("CN", "Contoso"),
+                ("OID.1.3.6.1.4.1.311.60.2.1.3", "US")
+            ]))
+        );
+        assert_eq!(
+            attribute_short_name("1.3.6.1.4.1.311.60.2.1.3"),
+            "OID.1.3.6.1.4.1.311.60.2.1.3"
+        );
+        assert_eq!(attribute_short_name("2.5.4.3"), "CN");
+    }
+
+    #[test]
+    fn rejects_malformed_distinguished_names() {
+        for name in [
+            "",
+            "=Contoso",
+            "Contoso",
+            "CN=Contoso, ",
+            "CN=Contoso, O",
+            "CN=\"Contoso",
+            "CN=\"Contoso\" Inc.",
+        ] {
+            assert_eq!(parse_distinguished_name(name), None, "{}", name);
+        }
+    }
+
+    #[test]
+    fn formats_distinguished_names() {
+        let pairs = attributes(&[
+            ("CN", "Contoso"),
+            ("O", "Contoso, Inc."),
+            ("OU", "Say \"hi\""),
+            ("L", " padded"),
+        ]);
+        let name = format_distinguished_name(&pairs);
+        assert_eq!(
+            name,
+            "CN=Contoso, O=\"Contoso, Inc.\", OU=\"Say \"\"hi\"\"\", L=\" padded\""
+        );
+        assert_eq!(parse_distinguished_name(&name), Some(pairs));
+    }
+
+    #[test]
+    fn encodes_distinguished_names() {
+        let encoded = encode_distinguished_name("CN=A, C=US").unwrap();
+        let expected = sequence(&[
+            der(
+                TAG_SET,
+                &sequence(&[oid("2.5.4.6").unwrap(), der(TAG_PRINTABLE_STRING, b"US")]),
+            ),
+            der(
+                TAG_SET,
+                &sequence(&[oid("2.5.4.3").unwrap(), der(TAG_UTF8_STRING, b"A")]),
+            ),
+        ]);
+        assert_eq!(encoded, expected);
+
+        assert!(encode_distinguished_name("CN=A, OID.2.5.4.3=B").is_ok());
+        for name in [
+            "CN=A, XX=B",
+            "CN=x, OID.99999999999999999999999=y",
+            "CN=x, OID.2=y",
+            "CN=x, OID.3.1=y",
+            "CN=x, OID.1.40=y",
+            "CN=x, OID.1..2=y",
+            "CN=x, OID.1.+2=y",
+            "CN=\"x",
+        ] {
+            assert!(encode_distinguished_name(name).is_err(), "{}", name);
+        }
+    }
+
+    #[test]
+    fn encodes_oids() {
+        assert_eq!(
+            oid("1.2.840.113549.1.1.11").unwrap(),
+            [0x06, 0x09, 0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x01, 0x0b]
+        );
+        assert_eq!(oid("2.5.4.3").unwrap(), [0x06, 0x03, 0x55, 0x04, 0x03]);
+        assert_eq!(oid("0.39").unwrap(), [0x06, 0x01, 0x27]);
+        // The second arc is unrestricted below 2
+        assert_eq!(oid("2.999.3").unwrap(), [0x06, 0x03, 0x88, 0x37, 0x03]);
+        assert_eq!(
+            oid("1.2.18446744073709551615").unwrap(),
+            [0x06, 0x0b, 0x2a, 0x81, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x7f]
+        );
+
+        for dotted in [
+            "",
+            "1",
+            "3.1",
+            "0.40",
+            "1.40",
+            "1..2",
+            "1.2.",
+            "1.-2",
+            "1.+2",
+            "1.2.18446744073709551616",
+            "2.18446744073709551615",
+        ] {
+            assert!(oid(dotted).is_err(), "{}", dotted);
+        }
+    }
+}
@@ -0,0 +1,250 @@
+//! Implements the project shapes required by different *windows-rs* versions.
+//!
+//! Up to version 0.23, *windows-rs* generates bindings through the `windows::build!`
+//! macro, invoked from the build script of a separate *bindings* crate. Later versions
+//! ship all bindings, selected through cargo features named after the namespaces, e.g.
+//! `UI_Xaml_Controls`. The built-in starters are written against the former. Starters
+//! providing a variant for the latter only support the range of versions it was written
+//! against, since the shape of the generated APIs kept changing.
+//!
+use anyhow::bail;
+
+/// First *windows-rs* version selecting APIs through cargo features, as `(major, minor)`.
+const FEATURES_VERSION: (u64, u64) = (0, 24);
+
+/// Range of *windows-rs* versions the cargo feature variants of the starters are written
+/// against, as inclusive `(major, minor)` bounds.
+pub(crate) const STARTER_FEATURES_VERSIONS: [(u64, u64); 2] = [(0, 48), (0, 51)];
+
+/// Returns whether *windows-rs* `version` selects APIs through cargo features, rather
+/// than through a *bindings* crate. Fails if `version` isn't a plain `major.minor[.patch]`
+/// version.
+///
+pub(crate) fn uses_features(version: &str) -> anyhow::Result<bool> {
+    Ok(major_minor(version)? >= FEATURES_VERSION)
+}
+
+/// Returns whether the cargo feature variants of the starters build against *windows-rs*
+/// `version`.
+///
+pub(crate) fn supports_starter_features(version: &str) -> anyhow::Result<bool> {
+    let [first, last] = STARTER_FEATURES_VERSIONS;
+    let version = major_minor(version)?;
+    Ok(first <= version && version <= last)
+}
+
+/// Parses `version` as `major.minor[.patch]`, returning `(major, minor)`.
+///
+fn major_minor(version: &str) -> anyhow::Result<(u64, u64)> {
+    let fields = version
+        .split('.')
+        .map(str::parse::<u64>)
+        .collect::<Result<Vec<_>, _>>();
+    match fields.as_deref() {
+        Ok([major, minor]) | Ok([major, minor, _]) => Ok((*major, *minor)),
+        _ => bail!(
+            "Invalid windows-rs version '{}' (expected <major>.<minor>[.<patch>])",
+            version
+        ),
+    }
+}
+
+/// Returns the cargo features enabling the types used by a crate: Those listed in the
+/// `windows::build!` invocation of `build_rs`, the build script of its *bindings* crate,
+/// and those imported from the *bindings* crate in `sources`. Adds `implement` if
+/// `sources` use `#[implement]`, as well as the `Foundation` namespaces the Windows
+/// Runtime and Win32 APIs depend on.
+///
+pub(crate) fn features(build_rs: &str, sources: &[&str]) -> Vec<String> {
+    let mut features = Vec::new();
+    let mut paths = braced_list(build_rs, "build!").unwrap_or_default();
+    for source in sources {
+        paths.extend(bindings_imports(source));
+    }
+    features.extend(paths.iter().filter_map(|path| namespace_feature(path)));
+    if features.iter().any(|feature| feature.starts_with("Win32_")) {
+        features.push("Win32_Foundation".to_owned());
+    }
+    if features
+        .iter()
+        .any(|feature| !feature.starts_with("Win32_"))
+    {
+        features.push("Foundation".to_owned());
+    }
+    if sources.iter().any(|source| source.contains("#[implement")) {
+        features.push("implement".to_owned());
+    }
+    features.sort();
+    features.dedup();
+    features
+}
+
+/// Returns the comma-separated entries of the braced list following `marker` in `text`,
+/// with whitespace removed. Nested braces are kept as part of their entry.
+///
+fn braced_list(text: &str, marker: &str) -> Option<Vec<String>> {
+    let text = &text[text.find(marker)? + marker.len()..];
+    let text = &text[text.find('{')? + 1..];
+
+    let mut entries = Vec::new();
+    let mut depth = 0;
+    let mut entry = String::new();
+    for c in text.chars() {
+        match c {
+            '{' => depth += 1,
+            '}' if depth == 0 => break,
+            '}' => depth -= 1,
+            ',' if depth == 0 => {
+                entries.push(std::mem::take(&mut entry));
+                continue;
+            }
+            _ => {}
+        }
+        if !c.is_whitespace() {
+            entry.push(c);
+        }
+    }
+    entries.push(entry);
+    entries.retain(|entry| !entry.is_empty());
+
+    Some(entries)
+}
+
+/// Returns the paths imported through `use bindings::...` statements in `source`.
+///
+fn bindings_imports(source: &str) -> Vec<String> {
+    let mut paths = Vec::new();
+    let mut rest = source;
+    while let Some(index) = rest.find("use bindings::") {
+        rest = &rest[index + "use bindings::".len()..];
+        if rest.starts_with('{') {
+            paths.extend(braced_list(rest, "").unwrap_or_default());
+        } else if let Some(end) = rest.find(';') {
+            paths.push(rest[..end].trim().to_owned());
+        }
+    }
+    paths
+}
+
+/// Returns the feature of the namespace of `path`, which is either `Namespace::Item` or
+/// `Namespace::{Item, ...}`.
+///
+fn namespace_feature(path: &str) -> Option<String> {
+    let namespace = match path.find("::{") {
+        Some(index) => &path[..index],
+        None => path.rsplit_once("::")?.0,
+    };
+    let namespace = namespace.strip_prefix("Windows::").unwrap_or(namespace);
+    if namespace == "Windows" {
+        return None;
+    }
+
+    Some(namespace.replace("::", "_"))
+}
+
+/// Returns the constructs in `source` that only exist up to *windows-rs* 0.23, and need to
+/// be ported by hand, as descriptions of what to change.
+///
+pub(crate) fn follow_ups(source: &str) -> Vec<&'static str> {
+    let mut follow_ups = Vec::new();
+    let mut rest = source;
+    while let Some(index) = rest.find("#[implement(") {
+        rest = &rest[index..];
+        let attribute = &rest[..rest.find(")]").unwrap_or(rest.len())];
+        if attribute.contains("extend ") || attribute.contains("override ") {
+            follow_ups.push(
+                "`#[implement(extend ..., override ...)]` derives from a XAML class, which later \
+                 versions don't support; implement the overrides another way",
+            );
+        } else if !source.contains("_Impl for ") {
+            follow_ups.push(
+                "`#[implement]` types provide their methods in inherent `impl` blocks; move \
+                 them into `impl <Interface>_Impl for <Type>` blocks",
+            );
+        }
+        rest = &rest[attribute.len()..];
+    }
+    for (pattern, follow_up) in [
+        (
+            "windows::build!",
+            "`windows::build!` no longer exists; enable cargo features of the windows crate \
+             instead",
+        ),
+        (
+            "().new()",
+            "`#[implement]` types are no longer instantiated with `new()`; convert them into \
+             their interface with `into()`",
+        ),
+        (
+            "IInspectable::try_from(",
+            "`IInspectable::try_from` no longer boxes values; use \
+             `PropertyValue::CreateString` and the like",
+        ),
+        (
+            "std::ptr::null_mut()",
+            "optional pointer arguments are `Option`s in later versions, e.g. \
+             `CoInitializeEx(None, ...)`",
+        ),
+    ] {
+        if source.contains(pattern) {
+            follow_ups.push(follow_up);
+        }
+    }
+    follow_ups.dedup();
+    follow_ups
+}
+
+/// Converts Rust source from using the *bindings* crate to using the *windows* crate
+/// directly: Bindings are imported from `windows`, and the support library from
+/// `windows::core`.
+///
+pub(crate) fn port_source(source: &str) -> String {
+    let mut ported = String::with_capacity(source.len());
+    let mut in_bindings_use = false;
+    for line in source.split_inclusive('\n') {
+        let content = line.trim_end_matches(['\r', '\n']);
+        let ending = &line[content.len()..];
+        let line = content;
+        let trimmed = line.trim();
+        let indent = &line[..line.len() - line.trim_start().len()];
+        let line = if in_bindings_use {
+            in_bindings_use = !trimmed.starts_with("};");
+            // Paths are relative to `windows` within the import
+            replace_root(line, "")
+        } else if trimmed == "use bindings::*;" {
+            continue;
+        } else if trimmed == "use windows::*;" {
+            format!("{}use windows::core::*;", indent)
+        } else if let Some(list) = trimmed.strip_prefix("use bindings::{") {
+            in_bindings_use = !list.ends_with("};");
+            format!("{}use windows::{{{}", indent, replace_root(list, ""))
+        } else {
+            replace_root(
+                &line.replace("bindings::Windows::", "windows::"),
+                "windows::",
+            )
+        };
+        ported.push_str(&line);
+        ported.push_str(ending);
+    }
+    ported
+}
+
+/// Replaces the `Windows::` root namespace of all paths in `line` with `root`.
+///
+fn replace_root(line: &str, root: &str) -> String {
+    let mut replaced = String::with_capacity(line.len());
+    let mut rest = line;
+    while let Some(index) = rest.find("Windows::") {
+        let preceding = rest[..index].chars().next_back();
+        replaced.push_str(&rest[..index]);
+        if preceding.is_some_and(|c| c.is_alphanumeric() || c == '_') {
+            replaced.push_str("Windows::");
+        } else {
+            replaced.push_str(root);
+        }
+        rest = &rest[index + "Windows::".len()..];
+    }
+    replaced.push_str(rest);
+    replaced
+}
@@ -0,0 +1,343 @@
+//! Implements writing of application bundles (.appxbundle, .msixbundle).
+//!
+//! A bundle is a ZIP archive storing a set of packages (uncompressed), alongside a bundle
+//! manifest (*AppxMetadata/AppxBundleManifest.xml*) that describes each package and its
+//! location inside the archive. Like packages, bundles carry a block map and content
+//! types.
+//!
+use std::{
+    fs::{self, File},
+    io::BufWriter,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{anyhow, bail, Context};
+
+use crate::appx::{
+    self, BlockMap, Format, HashAlgorithm, PackageIdentity, Payload, APPX_BLOCK_MAP_NAME,
+    APPX_MANIFEST_NAME, BLOCK_MAP_CONTENT_TYPE, CONTENT_TYPES_NAME,
+};
+use crate::zip::{ZipArchive, ZipWriter};
+
+pub(crate) const BUNDLE_MANIFEST_NAME: &str = "AppxMetadata\\AppxBundleManifest.xml";
+
+const BUNDLE_NAMESPACE: &str = "http://schemas.microsoft.com/appx/2013/bundle";
+const BUNDLE_MANIFEST_CONTENT_TYPE: &str = "application/vnd.ms-appx.bundlemanifest+xml";
+
+/// Processor architectures in the order they appear in bundle file names.
+///
+const ARCH_ORDER: &[&str] = &["x86", "x64", "arm", "arm64", "neutral"];
+
+/// A package to be included in a bundle.
+///
+#[derive(Debug)]
+pub(crate) struct BundlePackage {
+    pub(crate) path: PathBuf,
+    pub(crate) identity: PackageIdentity,
+    languages: Vec<String>,
+}
+
+impl BundlePackage {
+    /// Reads the identity and resource languages from the manifest of the package at
+    /// `path`.
+    ///
+    pub(crate) fn read(path: &Path) -> anyhow::Result<Self> {
+        let archive =
+            ZipArchive::open(path).with_context(|| format!("Reading {}", path.display()))?;
+        let manifest = archive
+            .find(APPX_MANIFEST_NAME)
+            .ok_or_else(|| anyhow!("{} doesn't contain {}", path.display(), APPX_MANIFEST_NAME))?;
+        let manifest = String::from_utf8(archive.read(manifest)?)
+            .with_context(|| format!("Reading {} from {}", APPX_MANIFEST_NAME, path.display()))?;
+
+        let identity = PackageIdentity::from_manifest(&manifest)
+            .with_context(|| format!("Reading package identity of {}", path.display()))?;
+        let document = roxmltree::Document::parse(manifest.trim_start_matches('\u{feff}'))?;
+        let languages: Vec<_> = document
+            .descendants()
+            .filter(|node| {
+                node.has_tag_name("Resource")
+                    && node
+                        .parent_element()
+                        .is_some_and(|parent| parent.has_tag_name("Resources"))
+            })
+            .filter_map(|node| node.attribute("Language"))
+            .map(str::to_owned)
+            .collect();
+        if languages.is_empty() {
+            bail!("{} doesn't declare any resource languages", path.display());
+        }
+
+        Ok(Self {
+            path: path.to_owned(),
+            identity,
+            languages,
+        })
+    }
+}
+
+/// The identity and architectures of an existing bundle, as declared in its bundle
+/// manifest.
+///
+#[derive(Debug)]
+pub(crate) struct BundleInfo {
+    pub(crate) path: PathBuf,
+    pub(crate) name: String,
+    pub(crate) version: String,
+    pub(crate) archs: Vec<String>,
+}
+
+impl BundleInfo {
+    /// Reads the bundle manifest of the bundle at `path`.
+    ///
+    pub(crate) fn read(path: &Path) -> anyhow::Result<Self> {
+        let archive =
+            ZipArchive::open(path).with_context(|| format!("Reading {}", path.display()))?;
+        let manifest = archive
+            .find(&appx::part_name(BUNDLE_MANIFEST_NAME))
+            .ok_or_else(|| {
+                anyhow!(
+                    "{} doesn't contain {}",
+                    path.display(),
+                    BUNDLE_MANIFEST_NAME
+                )
+            })?;
+        let manifest = String::from_utf8(archive.read(manifest)?)
+            .with_context(|| format!("Reading {} from {}", BUNDLE_MANIFEST_NAME, path.display()))?;
+        let document = roxmltree::Document::parse(manifest.trim_start_matches('\u{feff}'))
+            .with_context(|| format!("Parsing bundle manifest of {}", path.display()))?;
+
+        let identity = document
+            .descendants()
+            .find(|node| node.has_tag_name("Identity"))
+            .ok_or_else(|| anyhow!("Bundle manifest of {} lacks an Identity", path.display()))?;
+        let attribute = |name| {
+            identity.attribute(name).map(str::to_owned).ok_or_else(|| {
+                anyhow!(
+                    "Bundle manifest of {} lacks the Identity {} attribute",
+                    path.display(),
+                    name
+                )
+            })
+        };
+        let name = attribute("Name")?;
+        let version = attribute("Version")?;
+
+        // Resource packages don't carry code, hence don't have symbols either
+        let mut archs: Vec<_> = document
+            .descendants()
+            .filter(|node| {
+                node.has_tag_name("Package")
+                    && node.attribute("Type").unwrap_or("application") == "application"
+            })
+            .filter_map(|node| node.attribute("Architecture"))
+            .map(str::to_owned)
+            .collect();
+        archs.sort_by_key(|arch| (arch_rank(arch), arch.clone()));
+        archs.dedup();
+        if archs.is_empty() {
+            bail!(
+                "{} doesn't contain any application packages",
+                path.display()
+            );
+        }
+
+        Ok(Self {
+            path: path.to_owned(),
+            name,
+            version,
+            archs,
+        })
+    }
+}
+
+/// Checks that all `packages` share the same identity name, publisher, and version, and
+/// target distinct architectures. Sorts `packages` by architecture.
+///
+pub(crate) fn validate_packages(packages: &mut [BundlePackage]) -> anyhow::Result<()> {
+    let first = match packages.first() {
+        Some(first) => &first.identity,
+        None => bail!("No packages to bundle"),
+    };
+    for package in packages.iter().skip(1) {
+        let identity = &package.identity;
+        for (attribute, expected, actual) in [
+            ("name", &first.name, &identity.name),
+            ("publisher", &first.publisher, &identity.publisher),
+            ("version", &first.version, &identity.version),
+        ] {
+            if expected != actual {
+                bail!(
+                    "Identity {} mismatch: {} declares '{}', {} declares '{}'",
+                    attribute,
+                    packages[0].path.display(),
+                    expected,
+                    package.path.display(),
+                    actual
+                );
+            }
+        }
+    }
+
+    packages.sort_by_key(|package| {
+        (
+            arch_rank(&package.identity.arch),
+            package.identity.arch.clone(),
+        )
+    });
+    for pair in packages.windows(2) {
+        if pair[0].identity.arch == pair[1].identity.arch {
+            bail!(
+                "{} and {} both target the {} architecture",
+                pair[0].path.display(),
+                pair[1].path.display(),
+                pair[0].identity.arch
+            );
+        }
+    }
+
+    Ok(())
+}
+
+/// Returns the conventional bundle file name
+/// `<name>_<version>_<arch1>_<arch2>_<...>.<extension>`. `packages` must have been
+/// validated.
+///
+pub(crate) fn bundle_file_name(packages: &[BundlePackage], format: Format) -> String {
+    let identity = &packages[0].identity;
+    let archs: Vec<_> = packages
+        .iter()
+        .map(|package| package.identity.arch.as_str())
+        .collect();
+    format!(
+        "{}_{}_{}.{}",
+        identity.name,
+        identity.version,
+        archs.join("_"),
+        format.bundle_extension()
+    )
+}
+
+/// Writes a bundle containing `packages` to `path`, hashing blocks using
+/// `hash_algorithm`. `packages` must have been validated.
+///
+pub(crate) fn write_bundle(
+    path: &Path,
+    packages: &[BundlePackage],
+    hash_algorithm: HashAlgorithm,
+) -> anyhow::Result<()> {
+    let result = write_bundle_unchecked(path, packages, hash_algorithm);
+    if result.is_err() {
+        // Don't leave a truncated bundle behind
+        let _ = fs::remove_file(path);
+    }
+    result
+}
+
+fn write_bundle_unchecked(
+    path: &Path,
+    packages: &[BundlePackage],
+    hash_algorithm: HashAlgorithm,
+) -> anyhow::Result<()> {
+    let output = File::create(path).with_context(|| format!("Creating {}", path.display()))?;
+    let mut zip = ZipWriter::new(BufWriter::new(output));
+    let mut block_map = BlockMap::new(hash_algorithm);
+    let mut package_elements = String::new();
+    let mut names = Vec::new();
+
+    // Packages are stored uncompressed, so that they can be streamed straight out of the
+    // bundle
+    for package in packages {
+        let name = package
+            .path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .ok_or_else(|| anyhow!("Invalid package file name {}", package.path.display()))?
+            .to_owned();
+        let data = fs::read(&package.path)
+            .with_context(|| format!("Reading {}", package.path.display()))?;
+        let payload = Payload::new(&data, false)?;
+        let entry = appx::write_entry(&mut zip, &appx::part_name(&name), &data, &payload)?;
+        block_map.add_file(&name, &data, entry.header_size, &payload);
+
+        package_elements.push_str(&format!(
+            "\r\n    <Package Type=\"application\" Version=\"{}\" Architecture=\"{}\" \
+             FileName=\"{}\" Offset=\"{}\" Size=\"{}\">\r\n      <Resources>",
+            appx::escape_xml(&package.identity.version),
+            appx::escape_xml(&package.identity.arch),
+            appx::escape_xml(&name),
+            entry.header_offset + entry.header_size,
+            entry.compressed_size
+        ));
+        for language in &package.languages {
+            package_elements.push_str(&format!(
+                "\r\n        <Resource Language=\"{}\"/>",
+                appx::escape_xml(language)
+            ));
+        }
+        package_elements.push_str("\r\n      </Resources>\r\n    </Package>");
+        names.push(name);
+    }
+
+    let identity = &packages[0].identity;
+    let manifest = format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\r\n\
+         <Bundle xmlns=\"{}\" SchemaVersion=\"1.0\">\r\n  \
+         <Identity Name=\"{}\" Publisher=\"{}\" Version=\"{}\"/>\r\n  \
+         <Packages>{}\r\n  </Packages>\r\n</Bundle>\r\n",
+        BUNDLE_NAMESPACE,
+        appx::escape_xml(&identity.name),
+        appx::escape_xml(&identity.publisher),
+        appx::escape_xml(&identity.version),
+        package_elements
+    );
+    let payload = Payload::new(manifest.as_bytes(), true)?;
+    let entry = appx::write_entry(
+        &mut zip,
+        &appx::part_name(BUNDLE_MANIFEST_NAME),
+        manifest.as_bytes(),
+        &payload,
+    )?;
+    block_map.add_file(
+        BUNDLE_MANIFEST_NAME,
+        manifest.as_bytes(),
+        entry.header_size,
+        &payload,
+    );
+
+    // Footprint files go last, with the content types concluding the archive
+    let block_map = block_map.to_xml();
+    let payload = Payload::new(block_map.as_bytes(), true)?;
+    appx::write_entry(
+        &mut zip,
+        APPX_BLOCK_MAP_NAME,
+        block_map.as_bytes(),
+        &payload,
+    )?;
+
+    let content_types = appx::content_types_xml(
+        names.iter().map(String::as_str),
+        &[
+            (BUNDLE_MANIFEST_NAME, BUNDLE_MANIFEST_CONTENT_TYPE),
+            (APPX_BLOCK_MAP_NAME, BLOCK_MAP_CONTENT_TYPE),
+        ],
+    );
+    let payload = Payload::new(content_types.as_bytes(), true)?;
+    appx::write_entry(
+        &mut zip,
+        CONTENT_TYPES_NAME,
+        content_types.as_bytes(),
+        &payload,
+    )?;
+
+    zip.finish()?;
+
+    Ok(())
+}
+
+fn arch_rank(arch: &str) -> usize {
+    ARCH_ORDER
+        .iter()
+        .position(|known| known.eq_ignore_ascii_case(arch))
+        .unwrap_or(ARCH_ORDER.len())
+}
@@ -1,17 +1,25 @@
 //! Provides wrappers to run individual cargo commands (e.g. `cargo new`).
 
 use std::{
-    ffi::OsStr,
-    io,
-    process::{Command, Stdio},
+    env,
+    ffi::{OsStr, OsString},
+    fs, io,
+    path::Path,
+    process::{self, Command, Stdio},
 };
 
-use anyhow::{anyhow, Context};
+use anyhow::{anyhow, bail, Context};
+
+/// Returns the `cargo new` command invoked by [`new`] for `args`.
+///
+pub(crate) fn new_command(args: impl Iterator<Item = impl AsRef<OsStr>>) -> Command {
+    let mut command = Command::new(cargo_executable());
+    command.args(["new", "--color", "always"]).args(args);
+    command
+}
 
 pub(crate) fn new(args: impl Iterator<Item = impl AsRef<OsStr>>) -> anyhow::Result<()> {
-    let mut child = Command::new("cargo")
-        .args(["new", "--color", "always"])
-        .args(args)
+    let mut child = new_command(args)
         .stdout(Stdio::piped())
         .stderr(Stdio::piped())
         .spawn()
@@ -39,3 +47,52 @@ pub(crate) fn new(args: impl Iterator<Item = impl AsRef<OsStr>>) -> anyhow::Resu
         Err(anyhow!("Failed to execute cargo new"))
     }
 }
+
+/// Returns the contents of the *Cargo.toml* file `cargo new` would generate for a package
+/// at `path`, without touching `path`. `args` are passed on to `cargo new`.
+///
+/// This runs `cargo new` in a scratch directory, using the same directory name.
+///
+pub(crate) fn new_manifest(
+    path: &Path,
+    args: impl Iterator<Item = impl AsRef<OsStr>>,
+) -> anyhow::Result<String> {
+    if path.exists() {
+        bail!("Destination {} already exists", path.display());
+    }
+    let dir_name = path
+        .file_name()
+        .ok_or_else(|| anyhow!("Invalid package path {}", path.display()))?;
+
+    let scratch_dir = env::temp_dir().join(format!("cargo-uwp-{}", process::id()));
+    let result = (|| {
+        fs::create_dir_all(&scratch_dir)
+            .with_context(|| format!("Creating directory {}", scratch_dir.display()))?;
+        let package_dir = scratch_dir.join(dir_name);
+        let output = Command::new(cargo_executable())
+            .args(["new", "--quiet", "--vcs", "none"])
+            .arg(&package_dir)
+            .args(args)
+            .output()
+            .context("Executing cargo new")?;
+        if !output.status.success() {
+            bail!(
+                "Failed to execute cargo new: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+        let manifest_file = package_dir.join("Cargo.toml");
+        fs::read_to_string(&manifest_file)
+            .with_context(|| format!("Reading {}", manifest_file.display()))
+    })();
+    let _ = fs::remove_dir_all(&scratch_dir);
+
+    result
+}
+
+/// Returns the cargo executable that invoked this command, falling back to whichever
+/// `cargo` is found on the `PATH`.
+///
+fn cargo_executable() -> OsString {
+    env::var_os("CARGO").unwrap_or_else(|| "cargo".into())
+}
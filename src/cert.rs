@@ -0,0 +1,138 @@
+//! Implements creation of self-signed code signing certificates.
+//!
+//! Packages must be signed by a certificate whose subject equals the package publisher.
+//! For side-loading during development, a self-signed certificate will do, once it has
+//! been imported into the *Trusted People* store of the local machine. The certificate
+//! is written twice: as a password protected PKCS #12 file (.pfx) holding the private
+//! key for signing, and as a plain DER encoded certificate (.cer) for import.
+//!
+use std::time::Duration;
+
+use anyhow::{anyhow, Context};
+use p12_keystore::{
+    Certificate, EncryptionAlgorithm, KeyStore, KeyStoreEntry, MacAlgorithm, PrivateKeyChain,
+};
+use rsa::{
+    pkcs8::{EncodePrivateKey, EncodePublicKey},
+    Pkcs1v15Sign, RsaPrivateKey, RsaPublicKey,
+};
+use sha2::{Digest, Sha256};
+use x509_cert::{der::Encode, time::Validity};
+
+use crate::asn1::{
+    self, algorithm_identifier, bit_string, boolean, der, integer, oid, sequence, TAG_BIT_STRING,
+    TAG_CONTEXT_0, TAG_CONTEXT_3, TAG_OCTET_STRING,
+};
+
+const KEY_SIZE: usize = 2048;
+
+const OID_SHA256_WITH_RSA_ENCRYPTION: &str = "1.2.840.113549.1.1.11";
+const OID_SUBJECT_KEY_IDENTIFIER: &str = "2.5.29.14";
+const OID_KEY_USAGE: &str = "2.5.29.15";
+const OID_BASIC_CONSTRAINTS: &str = "2.5.29.19";
+const OID_EXTENDED_KEY_USAGE: &str = "2.5.29.37";
+const OID_CODE_SIGNING: &str = "1.3.6.1.5.5.7.3.3";
+
+/// A newly created self-signed certificate, together with its private key.
+///
+pub(crate) struct SelfSignedCertificate {
+    key: RsaPrivateKey,
+    /// The DER encoded certificate.
+    certificate: Vec<u8>,
+}
+
+impl SelfSignedCertificate {
+    /// Generates a key pair, and a code signing certificate for `subject` that is valid
+    /// for `validity`, starting now.
+    ///
+    pub(crate) fn new(subject: &str, validity: Duration) -> anyhow::Result<Self> {
+        let name = asn1::encode_distinguished_name(subject)?;
+        let key = RsaPrivateKey::new(&mut rand::thread_rng(), KEY_SIZE)
+            .context("Generating the key pair")?;
+        let public_key = RsaPublicKey::from(&key).to_public_key_der()?;
+
+        // Serial numbers must be positive, and should be unpredictable
+        let mut serial_number = rand::random::<[u8; 16]>();
+        serial_number[0] &= 0x7f;
+
+        let key_identifier = &Sha256::digest(public_key.as_bytes())[..20];
+        let extensions = [
+            extension(OID_BASIC_CONSTRAINTS, true, sequence(&[]))?,
+            // digitalSignature only, i.e. the first bit of the bit string
+            extension(OID_KEY_USAGE, true, der(TAG_BIT_STRING, &[0x07, 0x80]))?,
+            extension(
+                OID_EXTENDED_KEY_USAGE,
+                false,
+                sequence(&[oid(OID_CODE_SIGNING)?]),
+            )?,
+            extension(
+                OID_SUBJECT_KEY_IDENTIFIER,
+                false,
+                der(TAG_OCTET_STRING, key_identifier),
+            )?,
+        ];
+
+        let signature_algorithm = algorithm_identifier(OID_SHA256_WITH_RSA_ENCRYPTION)?;
+        let tbs_certificate = sequence(&[
+            der(TAG_CONTEXT_0, &integer(2)),
+            asn1::unsigned_integer(&serial_number),
+            signature_algorithm.clone(),
+            name.clone(),
+            Validity::from_now(validity)
+                .map_err(|e| anyhow!("Invalid validity period: {}", e))?
+                .to_der()?,
+            name,
+            public_key.as_bytes().to_owned(),
+            der(TAG_CONTEXT_3, &sequence(&extensions)),
+        ]);
+        let signature = key.sign(
+            Pkcs1v15Sign::new::<Sha256>(),
+            &Sha256::digest(&tbs_certificate),
+        )?;
+        let certificate = sequence(&[tbs_certificate, signature_algorithm, bit_string(&signature)]);
+
+        Ok(Self { key, certificate })
+    }
+
+    /// Returns the DER encoded certificate, as stored in .cer files.
+    ///
+    pub(crate) fn to_der(&self) -> &[u8] {
+        &self.certificate
+    }
+
+    /// Returns the certificate and private key as a PKCS #12 file protected by
+    /// `password`, listed under `friendly_name`.
+    ///
+    pub(crate) fn to_pfx(&self, friendly_name: &str, password: &str) -> anyhow::Result<Vec<u8>> {
+        let key = self.key.to_pkcs8_der()?;
+        let certificate = Certificate::from_der(&self.certificate)
+            .map_err(|e| anyhow!("Encoding the certificate: {}", e))?;
+        let local_key_id = &Sha256::digest(&self.certificate)[..20];
+
+        let mut key_store = KeyStore::new();
+        key_store.add_entry(
+            friendly_name,
+            KeyStoreEntry::PrivateKeyChain(PrivateKeyChain::new(
+                key.as_bytes(),
+                local_key_id,
+                [certificate],
+            )),
+        );
+        // Older versions of Windows fail to import files using AES or SHA-256
+        key_store
+            .writer(password)
+            .encryption_algorithm(EncryptionAlgorithm::PbeWithShaAnd3KeyTripleDesCbc)
+            .mac_algorithm(MacAlgorithm::HmacSha1)
+            .write()
+            .map_err(|e| anyhow!("Encoding the PFX file: {}", e))
+    }
+}
+
+fn extension(extension_id: &str, critical: bool, value: Vec<u8>) -> anyhow::Result<Vec<u8>> {
+    let mut items = vec![oid(extension_id)?];
+    if critical {
+        items.push(boolean(true));
+    }
+    items.push(der(TAG_OCTET_STRING, &value));
+    Ok(sequence(&items))
+}
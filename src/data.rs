@@ -4,10 +4,10 @@ use crate::shared::{
     PACKAGE_DISPLAY_NAME_DEFAULT, PACKAGE_DISPLAY_NAME_KEY, PACKAGE_IDENTITY_NAME_DEFAULT,
     PACKAGE_IDENTITY_NAME_KEY, PACKAGE_IDENTITY_PUBLISHER_DEFAULT, PACKAGE_IDENTITY_PUBLISHER_KEY,
     PACKAGE_IDENTITY_VERSION_DEFAULT, PACKAGE_IDENTITY_VERSION_KEY, PACKAGE_PHONE_ID_DEFAULT,
-    PACKAGE_PHONE_ID_KEY, PACKAGE_PUBLISHER_DISPLAY_NAME_DEFAULT,
-    PACKAGE_PUBLISHER_DISPLAY_NAME_KEY, PACKAGE_VISUAL_DESCRIPTION_DEFAULT,
-    PACKAGE_VISUAL_DESCRIPTION_KEY, PACKAGE_VISUAL_DISPLAY_NAME_DEFAULT,
-    PACKAGE_VISUAL_DISPLAY_NAME_KEY,
+    PACKAGE_PHONE_ID_KEY, PACKAGE_PHONE_PUBLISHER_ID_DEFAULT, PACKAGE_PHONE_PUBLISHER_ID_KEY,
+    PACKAGE_PUBLISHER_DISPLAY_NAME_DEFAULT, PACKAGE_PUBLISHER_DISPLAY_NAME_KEY,
+    PACKAGE_VISUAL_DESCRIPTION_DEFAULT, PACKAGE_VISUAL_DESCRIPTION_KEY,
+    PACKAGE_VISUAL_DISPLAY_NAME_DEFAULT, PACKAGE_VISUAL_DISPLAY_NAME_KEY,
 };
 
 /// Version of the *window-rs* crate to be used. This version is used in the
@@ -15,6 +15,16 @@ use crate::shared::{
 ///
 pub(crate) const WINDOWS_RS_VERSION: &str = "0.18.0";
 
+/// Version of the *windows-rs* crate that `cargo uwp migrate windows-rs` moves packages to
+/// by default.
+///
+pub(crate) const WINDOWS_RS_MIGRATION_VERSION: &str = "0.48.0";
+
+/// Dependencies of the generated build script, as `(crate name, version)` pairs.
+///
+pub(crate) const BUILD_DEPENDENCIES: &[(&str, &str)] =
+    &[("roxmltree", "0.14.1"), ("toml", "0.5.8")];
+
 /// Rust toolchain related assets
 pub(crate) const RUST_TOOLCHAIN_TOML_FILENAME: &str = "rust-toolchain.toml";
 pub(crate) const RUST_TOOLCHAIN_TOML: &[u8] = include_bytes!("../data/rust-toolchain.toml");
@@ -52,8 +62,7 @@ pub(crate) const SQUARE_150_LOGO_PNG_FILENAME: &str = "Square150x150Logo.scale-2
 ///
 pub(crate) const TEMPLATES_DIR: &str = "templates";
 
-pub(crate) const APPX_MANIFEST_TEMPLATE: &[u8] =
-    include_bytes!("../data/templates/AppxManifest.xml");
+pub(crate) const APPX_MANIFEST_TEMPLATE: &str = include_str!("../data/templates/AppxManifest.xml");
 pub(crate) const APPX_MANIFEST_TEMPLATE_FILENAME: &str = "AppxManifest.xml";
 
 pub(crate) const FILE_MAPPINGS_TEMPLATE: &[u8] =
@@ -70,6 +79,12 @@ pub(crate) const BUILD_RS_FILENAME: &str = "main.rs";
 pub(crate) const SHARED_RS: &[u8] = include_bytes!("shared.rs");
 pub(crate) const SHARED_RS_FILENAME: &str = "shared.rs";
 
+pub(crate) const TEMPLATE_RS: &[u8] = include_bytes!("../data/.build/template.rs");
+pub(crate) const TEMPLATE_RS_FILENAME: &str = "template.rs";
+
+pub(crate) const CAPABILITIES_RS: &[u8] = include_bytes!("../data/.build/capabilities.rs");
+pub(crate) const CAPABILITIES_RS_FILENAME: &str = "capabilities.rs";
+
 /// Package metatdata table used by the build system and this tool
 ///
 /// The string literal gets appended to the existing *Cargo.toml* file. Prior to doing
@@ -78,9 +93,6 @@ pub(crate) const SHARED_RS_FILENAME: &str = "shared.rs";
 /// generates.
 ///
 pub(crate) const PACKAGE_METADATA_INIT: &str = r#"
-[build-dependencies]
-toml = "0.5.8"
-
 
 # Metadata driving the build system. Once created, you can freely fill in and change the
 # required entries, and have the build system take it from there.
@@ -108,13 +120,44 @@ $package-identity-publisher$
 # $package-identity-version$
 
 
-# The following entry controls whether to target Windows 10 Mobile.
+# The following entries control whether to target Windows 10 Mobile.
 
 # (String, optional) The phone product ID: A string representing the GUID of the Windows
 # 10 Mobile product. It is required when targeting Windows 10 Mobile.
 # When missing, no packages targeting Windows 10 Mobile are generated.
 # $package-phoneidentity-productid$
 
+# (String, optional) The phone publisher ID: A string representing the GUID of the
+# Windows 10 Mobile publisher. Only used when targeting Windows 10 Mobile.
+# When missing, this value defaults to all zeros.
+# $package-phoneidentity-publisherid$
+
+
+# The following entries select the devices the application targets, and the packages
+# it depends on.
+
+# (Array of tables, optional) The target device families: Each entry names a device
+# family, e.g. "Windows.Universal", "Windows.Desktop", or "Windows.Xbox", along with the
+# minimum OS version the application runs on, and the maximum OS version it was tested
+# against. Versions are strings of four period-delimited numeric values.
+# When missing, the application targets "Windows.Universal" from version 10.0.10240.0,
+# tested against 10.0.18362.0.
+# target-device-families = [
+#     { name = "Windows.Desktop", min-version = "10.0.17763.0", max-version-tested = "10.0.19041.0" },
+# ]
+
+# (Array, optional) The packages the application depends on, typically frameworks
+# delivered by the Microsoft Store. Each entry is either a table spelling out the package
+# `name`, `min-version`, and `publisher`, or one of the following presets:
+# * "vclibs": The Visual C++ runtime libraries, using the debug variant in debug builds
+# * "vclibs-debug": The debug variant of the Visual C++ runtime libraries
+# * "microsoft-ui-xaml-2.7", "microsoft-ui-xaml-2.8": WinUI 2.7 or 2.8
+# When missing, this value defaults to ["vclibs"].
+# package-dependencies = [
+#     "vclibs",
+#     { name = "Contoso.Framework", min-version = "1.0.0.0", publisher = "CN=Contoso" },
+# ]
+
 
 # The next two entries designate public-facing properties of the application. Either one
 # is displayed in the Microsoft Store.
@@ -131,6 +174,18 @@ $package-identity-publisher$
 $package-properties-publisherdisplayname$
 
 
+# Capabilities declare access to resources and devices the application requires.
+# See [App capability declarations](https://docs.microsoft.com/en-us/windows/uwp/packaging/app-capability-declarations)
+
+# (Array of strings, optional) General-use and restricted capabilities, e.g.
+# ["internetClient", "picturesLibrary"]. Restricted capabilities require approval when
+# submitting the application to the Microsoft Store.
+# capabilities = []
+
+# (Array of strings, optional) Device capabilities, e.g. ["microphone", "webcam"].
+# device-capabilities = []
+
+
 # Public facing aspects of an application package.
 # See [uap:VisualElements](https://docs.microsoft.com/en-us/uwp/schemas/appxpackage/uapmanifestschema/element-uap-visualelements)
 
@@ -151,6 +206,9 @@ pub(crate) const PACKAGE_IDENTITY_PUBLISHER_PLACEHOLDER: &str = "$package-identi
 pub(crate) const PACKAGE_IDENTITY_VERSION_PLACEHOLDER: &str = "$package-identity-version$";
 /// $package-phoneidentity-productid$ => PACKAGE_PHONE_ID_KEY = "PACKAGE_PHONE_ID_DEFAULT"
 pub(crate) const PACKAGE_PHONE_ID_PLACEHOLDER: &str = "$package-phoneidentity-productid$";
+/// $package-phoneidentity-publisherid$ => PACKAGE_PHONE_PUBLISHER_ID_KEY = "PACKAGE_PHONE_PUBLISHER_ID_DEFAULT"
+pub(crate) const PACKAGE_PHONE_PUBLISHER_ID_PLACEHOLDER: &str =
+    "$package-phoneidentity-publisherid$";
 /// $package-properties-displayname$ => PACKAGE_DISPLAY_NAME_KEY = "PACKAGE_DISPLAY_NAME_DEFAULT"
 pub(crate) const PACKAGE_DISPLAY_NAME_PLACEHOLDER: &str = "$package-properties-displayname$";
 /// $package-properties-publisherdisplayname$ => PACKAGE_PUBLISHER_DISPLAY_NAME_KEY = "PACKAGE_PUBLISHER_DISPLAY_NAME_DEFAULT"
@@ -187,6 +245,13 @@ pub(crate) const METADATA_PLACEHOLDERS: &[(&str, (&str, &str))] = &[
         PACKAGE_PHONE_ID_PLACEHOLDER,
         (PACKAGE_PHONE_ID_KEY, PACKAGE_PHONE_ID_DEFAULT),
     ),
+    (
+        PACKAGE_PHONE_PUBLISHER_ID_PLACEHOLDER,
+        (
+            PACKAGE_PHONE_PUBLISHER_ID_KEY,
+            PACKAGE_PHONE_PUBLISHER_ID_DEFAULT,
+        ),
+    ),
     (
         PACKAGE_DISPLAY_NAME_PLACEHOLDER,
         (PACKAGE_DISPLAY_NAME_KEY, PACKAGE_DISPLAY_NAME_DEFAULT),
@@ -218,13 +283,71 @@ pub(crate) const METADATA_PLACEHOLDERS: &[(&str, (&str, &str))] = &[
 ///
 pub(crate) const BINDINGS_CRATE_PATH: &str = "bindings";
 pub(crate) const BINDINGS_CARGO_TOML: &str = include_str!("../data/bindings/Cargo.toml_");
-pub(crate) const BINDINGS_BUILD_RS: &[u8] = include_bytes!("../data/bindings/build.rs_");
 pub(crate) const BINDINGS_SRC_LIB_RS: &[u8] = include_bytes!("../data/bindings/src/lib.rs_");
 
-/// Generated src/main.rs
+/// Starter applications, selected through `--template`. Each one provides the generated
+/// *src/main.rs*, the build script of the *bindings* crate listing the types it uses, and
+/// the entry point declared in *AppxManifest.xml*.
+///
+pub(crate) struct Starter {
+    pub(crate) name: &'static str,
+    pub(crate) description: &'static str,
+    pub(crate) src_main_rs: &'static [u8],
+    pub(crate) bindings_build_rs: &'static [u8],
+    pub(crate) entry_point: &'static str,
+    /// Variant for *windows-rs* versions selecting APIs through cargo features, if any.
+    pub(crate) features: Option<FeaturesStarter>,
+}
+
+/// The *src/main.rs* of a starter application written against the *windows-rs* versions
+/// in `bindings::STARTER_FEATURES_VERSIONS`, and the cargo features of the *windows*
+/// crate it requires.
 ///
-pub(crate) const SRC_MAIN_RS: &[u8] = include_bytes!("../data/src/main.rs");
+pub(crate) struct FeaturesStarter {
+    pub(crate) src_main_rs: &'static [u8],
+    pub(crate) windows_features: &'static [&'static str],
+}
+
+pub(crate) const STARTERS: &[Starter] = &[
+    Starter {
+        name: "xaml",
+        description: "XAML application showing a button",
+        src_main_rs: include_bytes!("../data/starters/xaml/src/main.rs"),
+        bindings_build_rs: include_bytes!("../data/starters/xaml/bindings/build.rs_"),
+        entry_point: "XamlApp.App",
+        features: None,
+    },
+    Starter {
+        name: "core",
+        description: "CoreApplication with an IFrameworkView, without XAML",
+        src_main_rs: include_bytes!("../data/starters/core/src/main.rs"),
+        bindings_build_rs: include_bytes!("../data/starters/core/bindings/build.rs_"),
+        entry_point: "CoreApp.App",
+        features: Some(FeaturesStarter {
+            src_main_rs: include_bytes!("../data/starters/core/features/src/main.rs"),
+            windows_features: &[
+                "implement",
+                "ApplicationModel_Core",
+                "Foundation",
+                "UI_Core",
+                "Win32_Foundation",
+                "Win32_System_Com",
+            ],
+        }),
+    },
+    Starter {
+        name: "swapchain",
+        description: "CoreWindow with a DXGI swap chain render loop",
+        src_main_rs: include_bytes!("../data/starters/swapchain/src/main.rs"),
+        bindings_build_rs: include_bytes!("../data/starters/swapchain/bindings/build.rs_"),
+        entry_point: "SwapChainApp.App",
+        features: None,
+    },
+];
+
+pub(crate) const DEFAULT_STARTER: &str = "xaml";
 
 /// Placeholders that get replaced during various operations, when copying from templates
 /// to the final artifacts.
 pub(crate) const WINDOWS_RS_VERSION_PLACEHOLDER: &str = "$windows-rs-version$";
+pub(crate) const ENTRY_POINT_PLACEHOLDER: &str = "$entry-point$";
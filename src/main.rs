@@ -1,14 +1,28 @@
-#![cfg(target_os = "windows")]
 #![forbid(unsafe_code)]
 
+use std::process;
+
 use structopt::StructOpt;
 
+mod appx;
+mod artifacts;
+mod asn1;
+mod bindings;
+mod bundle;
 mod cargo;
+mod cert;
 mod data;
+mod metadata;
 mod ops;
+mod pdb;
+mod plan;
 mod shared;
+mod sign;
+mod template;
+mod upload;
+mod zip;
 
-use ops::New;
+use ops::{Bundle, Cert, Init, Migrate, New, Pack, Sign, Sym, Upload};
 
 #[derive(Debug, StructOpt)]
 #[structopt(bin_name = "cargo")]
@@ -27,15 +41,41 @@ enum Opt {
 enum Subcommand {
     #[structopt(about = "Creates a new UWP cargo package")]
     New(New),
+    #[structopt(about = "Turns an existing cargo package into a UWP cargo package")]
+    Init(Init),
+    #[structopt(about = "Migrates a UWP cargo package to newer dependencies")]
+    Migrate(Migrate),
+    #[structopt(about = "Creates an application package from the build artifacts")]
+    Pack(Pack),
+    #[structopt(about = "Bundles the packages of all target architectures")]
+    Bundle(Bundle),
+    #[structopt(about = "Creates a symbol archive (.appxsym) holding public symbols only")]
+    Sym(Sym),
+    #[structopt(about = "Creates an upload file for Store submission from the bundle and symbols")]
+    Upload(Upload),
+    #[structopt(about = "Signs a package or bundle using a code signing certificate")]
+    Sign(Sign),
+    #[structopt(about = "Manages code signing certificates")]
+    Cert(Cert),
 }
 
 fn main() {
     let Opt::Uwp { subcommand } = Opt::from_args();
     let result = match subcommand {
         Subcommand::New(new) => new.perform(),
+        Subcommand::Init(init) => init.perform(),
+        Subcommand::Migrate(migrate) => migrate.perform(),
+        Subcommand::Pack(pack) => pack.perform(),
+        Subcommand::Bundle(bundle) => bundle.perform(),
+        Subcommand::Sym(sym) => sym.perform(),
+        Subcommand::Upload(upload) => upload.perform(),
+        Subcommand::Sign(sign) => sign.perform(),
+        Subcommand::Cert(cert) => cert.perform(),
     };
 
     if let Err(ref e) = result {
-        println!("{}: {}", console::style("error").red().bright().bold(), e);
+        let label = console::style("error").for_stderr().red().bright().bold();
+        eprintln!("{}: {:#}", label, e);
+        process::exit(1);
     }
 }
@@ -0,0 +1,188 @@
+//! Implements validation and interactive entry of `[package.metadata.appxmanifest]`
+//! values.
+//!
+use std::io::{self, BufRead, Write};
+
+use anyhow::bail;
+use console::style;
+
+use crate::asn1;
+use crate::data::{METADATA_PLACEHOLDERS, PACKAGE_METADATA_INIT};
+use crate::shared::{
+    PACKAGE_DISPLAY_NAME_KEY, PACKAGE_IDENTITY_NAME_KEY, PACKAGE_IDENTITY_PUBLISHER_KEY,
+    PACKAGE_IDENTITY_VERSION_KEY, PACKAGE_PHONE_ID_KEY, PACKAGE_PHONE_PUBLISHER_ID_KEY,
+    PACKAGE_VISUAL_DESCRIPTION_KEY, PACKAGE_VISUAL_DISPLAY_NAME_KEY,
+};
+
+/// Fails if `value` would be rejected in an application manifest for the metadata `key`.
+/// The rules follow the respective types of the application manifest schema.
+///
+pub(crate) fn validate(key: &str, value: &str) -> anyhow::Result<()> {
+    match key {
+        PACKAGE_IDENTITY_NAME_KEY => {
+            // ST_PackageName, excluding reserved device names
+            let upper = value.to_uppercase();
+            let reserved = ["CON", "PRN", "AUX", "NUL"].contains(&upper.as_str())
+                || (upper.len() == 4
+                    && (upper.starts_with("COM") || upper.starts_with("LPT"))
+                    && upper.as_bytes()[3].is_ascii_digit());
+            if !(3..=50).contains(&value.len())
+                || !value
+                    .bytes()
+                    .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'.')
+                || reserved
+            {
+                bail!(
+                    "Invalid '{}' value '{}': Use 3 to 50 letters, digits, periods, or dashes",
+                    key,
+                    value
+                );
+            }
+        }
+        PACKAGE_IDENTITY_PUBLISHER_KEY => {
+            if value.len() > 8192 {
+                bail!("Invalid '{}' value: Exceeds 8192 characters", key);
+            }
+            if let Err(e) = asn1::encode_distinguished_name(value) {
+                bail!("Invalid '{}' value: {}", key, e);
+            }
+            if asn1::parse_distinguished_name(value)
+                .unwrap_or_default()
+                .iter()
+                .any(|(_, value)| value.is_empty())
+            {
+                bail!(
+                    "Invalid '{}' value '{}': Attribute values must not be empty",
+                    key,
+                    value
+                );
+            }
+        }
+        PACKAGE_IDENTITY_VERSION_KEY => {
+            let fields = value.split('.').collect::<Vec<_>>();
+            if fields.len() != 4 || fields.iter().any(|field| field.parse::<u16>().is_err()) {
+                bail!(
+                    "Invalid '{}' value '{}': Use four period-delimited numbers from 0 to 65535",
+                    key,
+                    value
+                );
+            }
+        }
+        PACKAGE_PHONE_ID_KEY | PACKAGE_PHONE_PUBLISHER_ID_KEY => {
+            let groups = value.split('-').map(str::len).collect::<Vec<_>>();
+            if groups != [8, 4, 4, 4, 12]
+                || !value.chars().all(|c| c == '-' || c.is_ascii_hexdigit())
+            {
+                bail!(
+                    "Invalid '{}' value '{}': Use a GUID such as 00000000-0000-0000-0000-000000000000",
+                    key,
+                    value
+                );
+            }
+        }
+        _ => {
+            let max_len = if key == PACKAGE_VISUAL_DESCRIPTION_KEY {
+                2048
+            } else {
+                256
+            };
+            if value.trim().is_empty() || value.trim() != value {
+                bail!(
+                    "Invalid '{}' value '{}': Must not be empty or start or end with whitespace",
+                    key,
+                    value
+                );
+            }
+            if value.chars().count() > max_len || value.chars().any(char::is_control) {
+                bail!(
+                    "Invalid '{}' value '{}': Use up to {} characters, excluding control characters",
+                    key,
+                    value,
+                    max_len
+                );
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Prompts for every metadata key not contained in `given`, explaining each one with its
+/// documentation comment. Empty input keeps the default: Optional keys are left out, and
+/// display names default to `package_name`, if any. Returns the entered values.
+///
+pub(crate) fn prompt(
+    given: &[(&'static str, String)],
+    package_name: Option<&str>,
+) -> anyhow::Result<Vec<(&'static str, String)>> {
+    let stdin = io::stdin();
+    let mut input = stdin.lock();
+    let mut values = Vec::new();
+    for (placeholder, (key, default)) in METADATA_PLACEHOLDERS {
+        if given.iter().any(|(given, _)| given == key) {
+            continue;
+        }
+
+        let (comment, optional) = explanation(placeholder);
+        let default = match (*key, package_name) {
+            _ if optional => None,
+            (PACKAGE_DISPLAY_NAME_KEY | PACKAGE_VISUAL_DISPLAY_NAME_KEY, Some(name)) => Some(name),
+            _ => Some(*default),
+        };
+        eprintln!();
+        for line in comment {
+            eprintln!("{}", style(line).dim());
+        }
+        loop {
+            match default {
+                Some(default) => eprint!("{} [{}]: ", style(key).bold(), default),
+                None => eprint!("{} [not set]: ", style(key).bold()),
+            }
+            io::stderr().flush()?;
+
+            let mut line = String::new();
+            if input.read_line(&mut line)? == 0 {
+                bail!("Unexpected end of input");
+            }
+            let value = line.trim_end_matches(['\r', '\n']);
+            if value.is_empty() {
+                break;
+            }
+            match validate(key, value) {
+                Ok(()) => {
+                    values.push((*key, value.to_owned()));
+                    break;
+                }
+                Err(e) => eprintln!("{}: {}", style("error").red().bright().bold(), e),
+            }
+        }
+    }
+    eprintln!();
+
+    Ok(values)
+}
+
+/// Returns the documentation comment preceding `placeholder` in the metadata template,
+/// and whether the key is optional, i.e. commented out by default.
+///
+fn explanation(placeholder: &str) -> (Vec<&'static str>, bool) {
+    let lines = PACKAGE_METADATA_INIT.lines().collect::<Vec<_>>();
+    let commented_out = format!("# {}", placeholder);
+    let index = match lines
+        .iter()
+        .position(|line| *line == placeholder || *line == commented_out)
+    {
+        Some(index) => index,
+        None => return (Vec::new(), false),
+    };
+    let start = lines[..index]
+        .iter()
+        .rposition(|line| !line.starts_with('#'))
+        .map_or(0, |blank| blank + 1);
+    let comment = lines[start..index]
+        .iter()
+        .map(|line| line.trim_start_matches('#').trim_start())
+        .collect();
+
+    (comment, lines[index] == commented_out)
+}
@@ -1,211 +1,1852 @@
 //! Implements the operations exposed through the CLI
 
-use std::ffi::OsStr;
-use std::path::Path;
-use std::{fs, path::PathBuf};
+use std::ffi::{OsStr, OsString};
+use std::fs::File;
+use std::io::{self, BufWriter, IsTerminal};
+use std::path::{Component, Path};
+use std::time::Duration;
+use std::{env, fs, path::PathBuf};
 
+use anyhow::{anyhow, bail, Context};
+use console::Term;
 use structopt::StructOpt;
 use toml_edit as toml;
 
+use crate::appx::{self, Format, HashAlgorithm, PackageIdentity, APPX_MANIFEST_NAME};
+use crate::artifacts;
+use crate::bindings;
+use crate::bundle::{self, BundleInfo, BundlePackage};
 use crate::cargo;
+use crate::cert::SelfSignedCertificate;
 use crate::data::{
-    APPX_MANIFEST_TEMPLATE, APPX_MANIFEST_TEMPLATE_FILENAME, ASSETS_DIR, BINDINGS_BUILD_RS,
-    BINDINGS_CARGO_TOML, BINDINGS_CRATE_PATH, BINDINGS_SRC_LIB_RS, BUILD_DIR, BUILD_RS,
-    BUILD_RS_FILENAME, CARGO_CONFIG_DIR, CARGO_CONFIG_TOML, CARGO_CONFIG_TOML_FILENAME,
+    Starter, APPX_MANIFEST_TEMPLATE, APPX_MANIFEST_TEMPLATE_FILENAME, ASSETS_DIR,
+    BINDINGS_CARGO_TOML, BINDINGS_CRATE_PATH, BINDINGS_SRC_LIB_RS, BUILD_DEPENDENCIES, BUILD_DIR,
+    BUILD_RS, BUILD_RS_FILENAME, CAPABILITIES_RS, CAPABILITIES_RS_FILENAME, CARGO_CONFIG_DIR,
+    CARGO_CONFIG_TOML, CARGO_CONFIG_TOML_FILENAME, DEFAULT_STARTER, ENTRY_POINT_PLACEHOLDER,
     FILE_MAPPINGS_TEMPLATE, FILE_MAPPINGS_TEMPLATE_FILENAME, METADATA_PLACEHOLDERS,
     PACKAGE_METADATA_INIT, RUST_TOOLCHAIN_TOML, RUST_TOOLCHAIN_TOML_FILENAME, SHARED_RS,
     SHARED_RS_FILENAME, SPLASH_SCREEN_PNG, SPLASH_SCREEN_PNG_FILENAME, SQUARE_150_LOGO_PNG,
-    SQUARE_150_LOGO_PNG_FILENAME, SQUARE_44_LOGO_PNG, SQUARE_44_LOGO_PNG_FILENAME, SRC_MAIN_RS,
-    STORE_LOGO_PNG, STORE_LOGO_PNG_FILENAME, TEMPLATES_DIR, WINDOWS_RS_VERSION,
-    WINDOWS_RS_VERSION_PLACEHOLDER,
+    SQUARE_150_LOGO_PNG_FILENAME, SQUARE_44_LOGO_PNG, SQUARE_44_LOGO_PNG_FILENAME, STARTERS,
+    STORE_LOGO_PNG, STORE_LOGO_PNG_FILENAME, TEMPLATES_DIR, TEMPLATE_RS, TEMPLATE_RS_FILENAME,
+    WINDOWS_RS_MIGRATION_VERSION, WINDOWS_RS_VERSION, WINDOWS_RS_VERSION_PLACEHOLDER,
 };
+use crate::metadata;
+use crate::pdb;
+use crate::plan::{MessageFormat, Plan};
+use crate::shared::{
+    PACKAGE_DISPLAY_NAME_KEY, PACKAGE_IDENTITY_NAME_KEY, PACKAGE_IDENTITY_PUBLISHER_KEY,
+    PACKAGE_PUBLISHER_DISPLAY_NAME_KEY, PACKAGE_VISUAL_DESCRIPTION_KEY,
+    PACKAGE_VISUAL_DISPLAY_NAME_KEY,
+};
+use crate::sign::{self, SigningCertificate};
+use crate::template::{self, TEMPLATE_DIR_ENV};
+use crate::upload;
+use crate::zip::ZipWriter;
+
+/// Environment variable holding the password of PFX files.
+const CERTIFICATE_PASSWORD_ENV: &str = "CARGO_UWP_CERTIFICATE_PASSWORD";
 
 #[derive(Debug, StructOpt)]
 pub(crate) struct New {
     #[structopt(parse(from_os_str))]
     path: PathBuf,
+    #[structopt(
+        long,
+        help = "Set the resulting package name, defaults to the directory name"
+    )]
+    name: Option<String>,
+    #[structopt(
+        long,
+        help = "Initialize a new repository for the given version control system (git, hg, pijul, or fossil) or do not initialize any version control at all (none)"
+    )]
+    vcs: Option<String>,
+    #[structopt(long, help = "Edition to set for the crate generated")]
+    edition: Option<String>,
+    #[structopt(long, help = "Registry to use")]
+    registry: Option<String>,
+    #[structopt(
+        long,
+        default_value = WINDOWS_RS_VERSION,
+        help = "Version of the windows crate; versions from 0.24 select APIs through cargo features instead of a bindings crate, which the core template supports for 0.48 through 0.51"
+    )]
+    windows_rs: String,
+    #[structopt(
+        long,
+        default_value = DEFAULT_STARTER,
+        help = "Starter application: xaml (XAML button), core (CoreApplication without XAML), or swapchain (CoreWindow with a DXGI swap chain render loop)"
+    )]
+    template: String,
+    #[structopt(
+        long,
+        parse(from_os_str),
+        env = TEMPLATE_DIR_ENV,
+        help = "Directory mirroring the built-in data layout, whose files override or add to the generated ones"
+    )]
+    template_dir: Option<PathBuf>,
+    #[structopt(flatten)]
+    identity: Identity,
+    #[structopt(
+        short,
+        long,
+        help = "Prompt for the package metadata (skipped unless stdin is a terminal)"
+    )]
+    interactive: bool,
+    #[structopt(long, help = "Print what would be done without doing it")]
+    dry_run: bool,
+    #[structopt(
+        long,
+        default_value = "human",
+        help = "Output format of --dry-run (human or json)"
+    )]
+    message_format: MessageFormat,
 }
 
 impl New {
     pub(crate) fn perform(&self) -> anyhow::Result<()> {
+        self.identity.validate()?;
+        let starter = find_starter(&self.template)?;
+        let mut values = self.identity.values();
+        if self.interactive {
+            if io::stdin().is_terminal() {
+                let package_name = self
+                    .name
+                    .clone()
+                    .or_else(|| Some(self.path.file_name()?.to_string_lossy().into_owned()));
+                let entered = metadata::prompt(&values, package_name.as_deref())?;
+                values.extend(entered);
+            } else {
+                warning("Standard input is not a terminal, skipping interactive mode");
+            }
+        }
+        if let Some(template_dir) = &self.template_dir {
+            template::check_dir(template_dir)?;
+        }
+        let options =
+            PackageOptions::new(starter, &self.windows_rs, self.template_dir.clone(), values)?;
+
         let package_root = PathBuf::from(&self.path);
-        cargo::new([&self.path].iter())?;
+        let args =
+            std::iter::once(self.path.clone().into_os_string()).chain(self.cargo_new_options(true));
+        if self.dry_run {
+            // Version control doesn't affect the generated Cargo.toml
+            let manifest =
+                cargo::new_manifest(&package_root, self.cargo_new_options(false).iter())?;
+            let mut plan = Plan::new();
+            plan.set_command(&cargo::new_command(args));
+            Self::plan(&package_root, &manifest, &options, &mut plan)?;
+            plan.print(self.message_format);
+            return Ok(());
+        }
+
+        cargo::new(args)?;
 
         // At this point the package directory should exist, so we could use
         // `canonicalize` if we ever need a fully qualified path name, e.g.:
         // let path = package_root.canonicalize()?;
         // println!("path: {:?}", &path);
 
-        // Write toolchain file
-        write_file(
-            &package_root,
-            None,
-            RUST_TOOLCHAIN_TOML_FILENAME,
-            RUST_TOOLCHAIN_TOML,
+        let result = Self::generate(&package_root, &options);
+        if result.is_err() {
+            // Don't leave a half-generated package behind. The directory didn't exist
+            // prior to running `cargo new`.
+            let _ = fs::remove_dir_all(&package_root);
+        }
+        result
+    }
+
+    fn generate(package_root: &Path, options: &PackageOptions) -> anyhow::Result<()> {
+        let manifest = read_cargo_toml(package_root)?;
+        let mut plan = Plan::new();
+        Self::plan(package_root, &manifest, options, &mut plan)?;
+        plan.check_conflicts(false)?;
+        plan.apply()
+    }
+
+    /// Returns the options passed on to `cargo new`, optionally including `--vcs`.
+    ///
+    fn cargo_new_options(&self, with_vcs: bool) -> Vec<OsString> {
+        let vcs = self.vcs.as_ref().filter(|_| with_vcs);
+        let mut options = Vec::new();
+        for (option, value) in &[
+            ("--name", self.name.as_ref()),
+            ("--vcs", vcs),
+            ("--edition", self.edition.as_ref()),
+            ("--registry", self.registry.as_ref()),
+        ] {
+            if let Some(value) = value {
+                options.push(option.into());
+                options.push(value.into());
+            }
+        }
+        options
+    }
+
+    /// Adds all writes following `cargo new` to `plan`, where `manifest` is the
+    /// *Cargo.toml* file generated by `cargo new`.
+    ///
+    fn plan(
+        package_root: &Path,
+        manifest: &str,
+        options: &PackageOptions,
+        plan: &mut Plan,
+    ) -> anyhow::Result<()> {
+        // Replace the sample source generated by `cargo new`
+        plan.update(
+            package_root.join("src").join("main.rs"),
+            options.src_main_rs(),
+        );
+
+        generate_package(package_root, manifest, options, plan)
+    }
+}
+
+#[derive(Debug, StructOpt)]
+pub(crate) struct Init {
+    #[structopt(
+        parse(from_os_str),
+        default_value = ".",
+        help = "Root directory of the existing cargo package"
+    )]
+    path: PathBuf,
+    #[structopt(long, help = "Overwrite existing files")]
+    force: bool,
+    #[structopt(
+        long,
+        default_value = DEFAULT_STARTER,
+        help = "Starter application: xaml (XAML button), core (CoreApplication without XAML), or swapchain (CoreWindow with a DXGI swap chain render loop)"
+    )]
+    template: String,
+    #[structopt(
+        long,
+        parse(from_os_str),
+        env = TEMPLATE_DIR_ENV,
+        help = "Directory mirroring the built-in data layout, whose files override or add to the generated ones"
+    )]
+    template_dir: Option<PathBuf>,
+    #[structopt(flatten)]
+    identity: Identity,
+    #[structopt(long, help = "Print what would be done without doing it")]
+    dry_run: bool,
+    #[structopt(
+        long,
+        default_value = "human",
+        help = "Output format of --dry-run (human or json)"
+    )]
+    message_format: MessageFormat,
+}
+
+impl Init {
+    pub(crate) fn perform(&self) -> anyhow::Result<()> {
+        self.identity.validate()?;
+        if let Some(template_dir) = &self.template_dir {
+            template::check_dir(template_dir)?;
+        }
+        let options = PackageOptions::new(
+            find_starter(&self.template)?,
+            WINDOWS_RS_VERSION,
+            self.template_dir.clone(),
+            self.identity.values(),
         )?;
+        let package_root = PathBuf::from(&self.path);
+        if !package_root.join("Cargo.toml").is_file() {
+            bail!(
+                "{} is not a cargo package (no Cargo.toml found)",
+                package_root.display()
+            );
+        }
+
+        let mut plan = Plan::new();
 
-        // Expand placeholders in `package.metadata` table
-        let mut metadata = PACKAGE_METADATA_INIT.to_owned();
-        for (from, (k, v)) in METADATA_PLACEHOLDERS {
-            metadata = metadata.replace(from, &format!("{} = \"{}\"", k, v));
+        // Never touch existing sources; only provide a sample if there's nothing yet
+        if !package_root.join("src").join("main.rs").exists() {
+            plan.write(
+                &package_root,
+                Some(&["src"]),
+                "main.rs",
+                options.src_main_rs(),
+            );
         }
-        // Append `package.metadata` to *Cargo.toml*
-        let mut cargo_toml = PathBuf::from(&package_root);
-        cargo_toml.push("Cargo.toml");
-        let mut contents = fs::read(&cargo_toml)?;
-        contents.extend_from_slice(metadata.as_bytes());
-        fs::write(&cargo_toml, contents)?;
 
-        // Write default cargo configuration
-        write_file(
+        generate_package(
             &package_root,
-            Some(&[CARGO_CONFIG_DIR]),
-            CARGO_CONFIG_TOML_FILENAME,
-            CARGO_CONFIG_TOML,
+            &read_cargo_toml(&package_root)?,
+            &options,
+            &mut plan,
         )?;
+        if self.dry_run {
+            plan.print(self.message_format);
+            return Ok(());
+        }
+        plan.check_conflicts(self.force)?;
+        plan.apply()?;
+        status("Initialized", &package_root.display());
+
+        Ok(())
+    }
+}
+
+/// Package identity and display properties written into the
+/// `[package.metadata.appxmanifest]` table in place of the defaults.
+///
+#[derive(Debug, StructOpt)]
+pub(crate) struct Identity {
+    #[structopt(
+        long,
+        help = "Package identity name, as found in the Microsoft Partner Center"
+    )]
+    identity_name: Option<String>,
+    #[structopt(
+        long,
+        help = "Package publisher, a distinguished name such as CN=<publisher ID>"
+    )]
+    publisher: Option<String>,
+    #[structopt(long, help = "Publisher display name, as shown in the Microsoft Store")]
+    publisher_display_name: Option<String>,
+    #[structopt(long, help = "Application display name, defaults to the package name")]
+    display_name: Option<String>,
+    #[structopt(long, help = "Application description")]
+    description: Option<String>,
+}
+
+impl Identity {
+    /// Fails if any of the given values would be rejected in an application manifest.
+    ///
+    fn validate(&self) -> anyhow::Result<()> {
+        for (key, value) in self.values() {
+            metadata::validate(key, &value)?;
+        }
+
+        Ok(())
+    }
 
-        // Create bindings crate
+    /// Returns the `[package.metadata.appxmanifest]` keys set through the command line,
+    /// along with their values.
+    ///
+    fn values(&self) -> Vec<(&'static str, String)> {
+        [
+            (PACKAGE_IDENTITY_NAME_KEY, &self.identity_name),
+            (PACKAGE_IDENTITY_PUBLISHER_KEY, &self.publisher),
+            (
+                PACKAGE_PUBLISHER_DISPLAY_NAME_KEY,
+                &self.publisher_display_name,
+            ),
+            (PACKAGE_DISPLAY_NAME_KEY, &self.display_name),
+            (PACKAGE_VISUAL_DISPLAY_NAME_KEY, &self.display_name),
+            (PACKAGE_VISUAL_DESCRIPTION_KEY, &self.description),
+        ]
+        .iter()
+        .filter_map(|(key, value)| Some((*key, value.as_ref()?.clone())))
+        .collect()
+    }
+}
+
+/// Choices shaping the generated package, shared by `new` and `init`.
+///
+struct PackageOptions {
+    starter: &'static Starter,
+    /// Version of the *windows* crate.
+    windows_rs: String,
+    /// The cargo features of the *windows* crate used by the starter, or `None` if
+    /// `windows_rs` requires a *bindings* crate.
+    windows_features: Option<Vec<String>>,
+    /// User-supplied files overriding or adding to the built-in ones.
+    template_dir: Option<PathBuf>,
+    /// `[package.metadata.appxmanifest]` values replacing the defaults.
+    metadata: Vec<(&'static str, String)>,
+}
+
+impl PackageOptions {
+    fn new(
+        starter: &'static Starter,
+        windows_rs: &str,
+        template_dir: Option<PathBuf>,
+        metadata: Vec<(&'static str, String)>,
+    ) -> anyhow::Result<Self> {
+        let windows_features = if bindings::uses_features(windows_rs)? {
+            let features = starter.features.as_ref().ok_or_else(|| {
+                anyhow!(
+                    "The '{}' template requires windows-rs 0.23 or earlier, not {}; use --template core with later versions",
+                    starter.name,
+                    windows_rs
+                )
+            })?;
+            if !bindings::supports_starter_features(windows_rs)? {
+                let [(first_major, first_minor), (last_major, last_minor)] =
+                    bindings::STARTER_FEATURES_VERSIONS;
+                bail!(
+                    "The '{}' template supports windows-rs up to 0.23, or from {}.{} through {}.{}, not {}",
+                    starter.name,
+                    first_major,
+                    first_minor,
+                    last_major,
+                    last_minor,
+                    windows_rs
+                );
+            }
+            Some(
+                features
+                    .windows_features
+                    .iter()
+                    .map(|&feature| feature.to_owned())
+                    .collect(),
+            )
+        } else {
+            None
+        };
+
+        Ok(Self {
+            starter,
+            windows_rs: windows_rs.to_owned(),
+            windows_features,
+            template_dir,
+            metadata,
+        })
+    }
+
+    /// Returns the *src/main.rs* file of the starter, matching the *windows* crate version.
+    ///
+    fn src_main_rs(&self) -> Vec<u8> {
+        match (&self.windows_features, &self.starter.features) {
+            (Some(_), Some(features)) => features.src_main_rs.to_owned(),
+            _ => self.starter.src_main_rs.to_owned(),
+        }
+    }
+}
+
+/// Looks up the built-in starter application called `name`.
+///
+fn find_starter(name: &str) -> anyhow::Result<&'static Starter> {
+    STARTERS
+        .iter()
+        .find(|starter| starter.name == name)
+        .ok_or_else(|| {
+            let mut message = format!("Unknown template '{}', available templates are:", name);
+            for starter in STARTERS {
+                message.push_str(&format!("\n  {:<12}{}", starter.name, starter.description));
+            }
+            anyhow!(message)
+        })
+}
+
+fn read_cargo_toml(package_root: &Path) -> anyhow::Result<String> {
+    let manifest_file = package_root.join("Cargo.toml");
+    fs::read_to_string(&manifest_file)
+        .with_context(|| format!("Reading {}", manifest_file.display()))
+}
+
+/// Adds the files turning the cargo package at `package_root` into a UWP package to
+/// `plan`: The toolchain file, cargo configuration, bindings crate, assets, templates, and
+/// build script, as well as the required entries merged into `manifest`, the current
+/// contents of *Cargo.toml*.
+///
+fn generate_package(
+    package_root: &Path,
+    manifest: &str,
+    options: &PackageOptions,
+    plan: &mut Plan,
+) -> anyhow::Result<()> {
+    // Write toolchain file
+    plan.write(
+        package_root,
+        None,
+        RUST_TOOLCHAIN_TOML_FILENAME,
+        RUST_TOOLCHAIN_TOML,
+    );
+
+    // Write default cargo configuration
+    plan.write(
+        package_root,
+        Some(&[CARGO_CONFIG_DIR]),
+        CARGO_CONFIG_TOML_FILENAME,
+        CARGO_CONFIG_TOML,
+    );
+
+    // Create bindings crate, unless the windows crate is configured through features
+    if options.windows_features.is_none() {
         let content =
-            BINDINGS_CARGO_TOML.replace(WINDOWS_RS_VERSION_PLACEHOLDER, WINDOWS_RS_VERSION);
-        write_file(
-            &package_root,
+            BINDINGS_CARGO_TOML.replace(WINDOWS_RS_VERSION_PLACEHOLDER, &options.windows_rs);
+        plan.write(
+            package_root,
             Some(&[BINDINGS_CRATE_PATH]),
             "Cargo.toml",
             &content,
-        )?;
+        );
 
-        write_file(
-            &package_root,
+        plan.write(
+            package_root,
             Some(&[BINDINGS_CRATE_PATH]),
             "build.rs",
-            BINDINGS_BUILD_RS,
-        )?;
+            options.starter.bindings_build_rs,
+        );
 
-        write_file(
-            &package_root,
+        plan.write(
+            package_root,
             Some(&[BINDINGS_CRATE_PATH, "src"]),
             "lib.rs",
             BINDINGS_SRC_LIB_RS,
-        )?;
+        );
+    }
 
-        // Write sample source
-        write_file(&package_root, Some(&["src"]), "main.rs", SRC_MAIN_RS)?;
-
-        // Update Cargo.toml to include the bindings crate
-        let mut manifest_file = PathBuf::from(&package_root);
-        manifest_file.push("Cargo.toml");
-        let manifest = fs::read_to_string(&manifest_file)?;
-        let mut manifest: toml::Document = manifest.parse()?;
-
-        // TODO: Insert table if it doesn't exist
-        let dependencies = manifest["dependencies"].as_table_mut().unwrap();
-        let mut bindings = toml::InlineTable::default();
-        bindings.get_or_insert("path", "./bindings");
-        bindings.fmt();
-        dependencies["bindings"] = toml::value(toml::Value::InlineTable(bindings));
-        dependencies["windows"] = toml::value(toml::Value::from(WINDOWS_RS_VERSION));
-
-        // Add custom path to build script
-        let package = manifest["package"].as_table_mut().unwrap();
-        package["build"] = toml::value(toml::Value::from(format!(
-            "{}/{}",
-            BUILD_DIR, BUILD_RS_FILENAME
-        )));
+    // Files of the template directory override the built-in ones, except for its
+    // Cargo.toml, which gets merged into the manifest
+    let mut template_files = match &options.template_dir {
+        Some(template_dir) => template::files(
+            template_dir,
+            options.starter.name,
+            &template_placeholders(manifest, options)?,
+        )?,
+        None => Vec::new(),
+    };
+    let merged = match template_files
+        .iter()
+        .position(|(destination, _)| destination == Path::new("Cargo.toml"))
+    {
+        Some(index) => merge_template_manifest(
+            manifest,
+            &String::from_utf8(template_files.remove(index).1)
+                .context("Template Cargo.toml is not valid UTF-8")?,
+        )?,
+        None => manifest.to_owned(),
+    };
+    plan.update_manifest(
+        package_root.join("Cargo.toml"),
+        manifest,
+        update_cargo_toml(package_root, &merged, options)?,
+    )?;
 
-        fs::write(
-            &manifest_file,
-            manifest.to_string_in_original_order().as_bytes(),
-        )?;
+    // Generate Assets
+    plan.write(
+        package_root,
+        Some(&[ASSETS_DIR]),
+        STORE_LOGO_PNG_FILENAME,
+        STORE_LOGO_PNG,
+    );
+    plan.write(
+        package_root,
+        Some(&[ASSETS_DIR]),
+        SPLASH_SCREEN_PNG_FILENAME,
+        SPLASH_SCREEN_PNG,
+    );
+    plan.write(
+        package_root,
+        Some(&[ASSETS_DIR]),
+        SQUARE_44_LOGO_PNG_FILENAME,
+        SQUARE_44_LOGO_PNG,
+    );
+    plan.write(
+        package_root,
+        Some(&[ASSETS_DIR]),
+        SQUARE_150_LOGO_PNG_FILENAME,
+        SQUARE_150_LOGO_PNG,
+    );
 
-        // Generate Assets
-        write_file(
-            &package_root,
-            Some(&[ASSETS_DIR]),
-            STORE_LOGO_PNG_FILENAME,
-            STORE_LOGO_PNG,
-        )?;
-        write_file(
-            &package_root,
-            Some(&[ASSETS_DIR]),
-            SPLASH_SCREEN_PNG_FILENAME,
-            SPLASH_SCREEN_PNG,
-        )?;
-        write_file(
-            &package_root,
-            Some(&[ASSETS_DIR]),
-            SQUARE_44_LOGO_PNG_FILENAME,
-            SQUARE_44_LOGO_PNG,
-        )?;
-        write_file(
-            &package_root,
-            Some(&[ASSETS_DIR]),
-            SQUARE_150_LOGO_PNG_FILENAME,
-            SQUARE_150_LOGO_PNG,
-        )?;
+    // Generate templates used by the build system (implemented in the generated
+    // *build.rs* file)
+    plan.write(
+        package_root,
+        Some(&[TEMPLATES_DIR]),
+        APPX_MANIFEST_TEMPLATE_FILENAME,
+        APPX_MANIFEST_TEMPLATE.replace(ENTRY_POINT_PLACEHOLDER, options.starter.entry_point),
+    );
+    plan.write(
+        package_root,
+        Some(&[TEMPLATES_DIR]),
+        FILE_MAPPINGS_TEMPLATE_FILENAME,
+        FILE_MAPPINGS_TEMPLATE,
+    );
 
-        // Generate templates used by the build system (implemented in the generated
-        // *build.rs* file)
-        write_file(
-            &package_root,
-            Some(&[TEMPLATES_DIR]),
-            APPX_MANIFEST_TEMPLATE_FILENAME,
-            APPX_MANIFEST_TEMPLATE,
-        )?;
-        write_file(
-            &package_root,
-            Some(&[TEMPLATES_DIR]),
-            FILE_MAPPINGS_TEMPLATE_FILENAME,
-            FILE_MAPPINGS_TEMPLATE,
-        )?;
+    // Write build system file(s)
+    plan.write(
+        package_root,
+        Some(&[BUILD_DIR]),
+        BUILD_RS_FILENAME,
+        BUILD_RS,
+    );
+    plan.write(
+        package_root,
+        Some(&[BUILD_DIR]),
+        SHARED_RS_FILENAME,
+        SHARED_RS,
+    );
+    plan.write(
+        package_root,
+        Some(&[BUILD_DIR]),
+        TEMPLATE_RS_FILENAME,
+        TEMPLATE_RS,
+    );
+    plan.write(
+        package_root,
+        Some(&[BUILD_DIR]),
+        CAPABILITIES_RS_FILENAME,
+        CAPABILITIES_RS,
+    );
 
-        // Write build system file(s)
-        write_file(
-            &package_root,
-            Some(&[BUILD_DIR]),
-            BUILD_RS_FILENAME,
-            BUILD_RS,
+    for (destination, contents) in template_files {
+        plan.write(package_root, None, destination, contents);
+    }
+
+    Ok(())
+}
+
+/// Returns the placeholders expanded in the files of the template directory, along with
+/// their values for the package described by `manifest`. Metadata placeholders expand to
+/// entries of `[package.metadata.appxmanifest]`, the same as in the built-in manifest.
+///
+fn template_placeholders(
+    manifest: &str,
+    options: &PackageOptions,
+) -> anyhow::Result<Vec<(&'static str, String)>> {
+    let package_name = manifest
+        .parse::<toml::Document>()?
+        .as_table()
+        .get("package")
+        .and_then(|package| package["name"].as_str().map(str::to_owned));
+    let mut placeholders = vec![
+        (WINDOWS_RS_VERSION_PLACEHOLDER, options.windows_rs.clone()),
+        (
+            ENTRY_POINT_PLACEHOLDER,
+            options.starter.entry_point.to_owned(),
+        ),
+    ];
+    for (placeholder, (key, default)) in METADATA_PLACEHOLDERS {
+        let value = metadata_value(key, default, &options.metadata, package_name.as_deref());
+        placeholders.push((placeholder, metadata_entry(key, value)));
+    }
+
+    Ok(placeholders)
+}
+
+/// Merges the *Cargo.toml* of a template directory, `template`, into `manifest`. Keys of
+/// `template` replace those of `manifest`, and tables are merged recursively. Returns the
+/// merged contents.
+///
+fn merge_template_manifest(manifest: &str, template: &str) -> anyhow::Result<String> {
+    let mut manifest: toml::Document = manifest.parse()?;
+    let template: toml::Document = template.parse().context("Parsing template Cargo.toml")?;
+    // Tables keep their position from the document they were parsed from, so place those
+    // of the template after the ones of `manifest`
+    let offset = last_table_position(manifest.as_table()) + 1;
+    merge_tables(manifest.as_table_mut(), template.as_table(), offset);
+
+    Ok(manifest.to_string_in_original_order())
+}
+
+fn merge_tables(table: &mut toml::Table, other: &toml::Table, offset: usize) {
+    for (key, item) in other.iter() {
+        match (table.get_mut(key), item) {
+            (Some(toml::Item::Table(existing)), toml::Item::Table(other)) => {
+                merge_tables(existing, other, offset)
+            }
+            _ => {
+                let mut item = item.clone();
+                shift_positions(&mut item, offset);
+                table[key] = item;
+            }
+        }
+    }
+}
+
+/// Moves the tables in `item` by `offset` positions.
+///
+fn shift_positions(item: &mut toml::Item, offset: usize) {
+    match item {
+        toml::Item::Table(table) => shift_table_positions(table, offset),
+        toml::Item::ArrayOfTables(tables) => {
+            for index in 0..tables.len() {
+                if let Some(table) = tables.get_mut(index) {
+                    shift_table_positions(table, offset);
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+fn shift_table_positions(table: &mut toml::Table, offset: usize) {
+    if let Some(position) = table.position() {
+        table.set_position(position + offset);
+    }
+    for (_, item) in table.iter_mut() {
+        shift_positions(item, offset);
+    }
+}
+
+/// Adds the bindings crate, the *windows* crate, the build script, its dependencies, and
+/// the `[package.metadata.appxmanifest]` table to *Cargo.toml*, keeping entries that
+/// exist already. All edits go through a single document, preserving formatting and
+/// comments. Returns the updated contents of `manifest`.
+///
+fn update_cargo_toml(
+    package_root: &Path,
+    manifest: &str,
+    options: &PackageOptions,
+) -> anyhow::Result<String> {
+    let mut manifest: toml::Document = manifest
+        .parse()
+        .with_context(|| format!("Parsing {}", package_root.join("Cargo.toml").display()))?;
+
+    let mut bindings = toml::InlineTable::default();
+    bindings.get_or_insert("path", "./bindings");
+    bindings.fmt();
+    // Tables are written in the order of their position; append new ones, keeping the
+    // metadata table last
+    let end = last_table_position(manifest.as_table()) + 1;
+
+    let dependencies = table_mut(manifest.as_table_mut(), "dependencies", end)?;
+    match &options.windows_features {
+        None => {
+            merge_dependency(
+                dependencies,
+                "bindings",
+                toml::value(toml::Value::InlineTable(bindings)),
+            );
+            merge_dependency(
+                dependencies,
+                "windows",
+                toml::value(toml::Value::from(options.windows_rs.as_str())),
+            );
+        }
+        Some(features) => merge_dependency(
+            dependencies,
+            "windows",
+            windows_dependency(&options.windows_rs, features, end),
+        ),
+    }
+
+    let build_dependencies = table_mut(manifest.as_table_mut(), "build-dependencies", end)?;
+    for (name, version) in BUILD_DEPENDENCIES {
+        merge_dependency(
+            build_dependencies,
+            name,
+            toml::value(toml::Value::from(*version)),
+        );
+    }
+
+    // Add custom path to build script. An existing build script would no longer run, so
+    // make sure this doesn't go unnoticed.
+    let build_script = format!("{}/{}", BUILD_DIR, BUILD_RS_FILENAME);
+    let package = table_mut(manifest.as_table_mut(), "package", end)?;
+    match package["build"].as_str() {
+        Some(existing) if existing != build_script => warning(&format!(
+            "Replacing build script {} with {}; move its logic into the latter",
+            existing, build_script
+        )),
+        None if package_root.join("build.rs").is_file() => warning(&format!(
+            "Replacing build script build.rs with {}; move its logic into the latter",
+            build_script
+        )),
+        _ => {}
+    }
+    package["build"] = toml::value(toml::Value::from(build_script));
+
+    merge_metadata(package, &options.metadata, end + 1)?;
+
+    Ok(manifest.to_string_in_original_order())
+}
+
+/// Merges the `[package.metadata.appxmanifest]` table into `package`. When the table is
+/// missing it is inserted at `position` along with its documentation comments, otherwise
+/// only missing keys, and keys given in `values`, are added.
+///
+fn merge_metadata(
+    package: &mut toml::Table,
+    values: &[(&'static str, String)],
+    position: usize,
+) -> anyhow::Result<()> {
+    // Expand placeholders in `package.metadata` table. Values given on the command line
+    // replace the defaults (uncommenting optional keys), and display names default to the
+    // package name.
+    let package_name = package["name"].as_str().map(str::to_owned);
+    let mut metadata_init = PACKAGE_METADATA_INIT.to_owned();
+    for (from, (k, v)) in METADATA_PLACEHOLDERS {
+        if values.iter().any(|(key, _)| key == k) {
+            metadata_init = metadata_init.replace(&format!("# {}", from), from);
+        }
+        let v = metadata_value(k, v, values, package_name.as_deref());
+        metadata_init = metadata_init.replace(from, &metadata_entry(k, v));
+    }
+    let mut metadata_init: toml::Document = metadata_init.parse()?;
+    let mut appxmanifest_init = table_mut(metadata_init.as_table_mut(), "package", 0)
+        .and_then(|package| table_mut(package, "metadata", 0))?
+        .remove("appxmanifest")
+        .ok_or_else(|| anyhow!("Missing [package.metadata.appxmanifest] table"))?;
+
+    let metadata = match package.entry("metadata") {
+        item if item.is_none() => {
+            let mut metadata = toml::Table::new();
+            metadata.set_implicit(true);
+            item.or_insert(toml::Item::Table(metadata))
+        }
+        item => item,
+    }
+    .as_table_mut()
+    .ok_or_else(|| anyhow!("'package.metadata' in Cargo.toml is not a table"))?;
+
+    match metadata.get_mut("appxmanifest") {
+        None => {
+            // Tables keep their position from the document they were parsed from
+            if let Some(table) = appxmanifest_init.as_table_mut() {
+                table.set_position(position);
+            }
+            metadata["appxmanifest"] = appxmanifest_init;
+        }
+        Some(appxmanifest) => {
+            let appxmanifest = appxmanifest.as_table_mut().ok_or_else(|| {
+                anyhow!("'package.metadata.appxmanifest' in Cargo.toml is not a table")
+            })?;
+            if let Some(appxmanifest_init) = appxmanifest_init.as_table() {
+                for (key, value) in appxmanifest_init.iter() {
+                    if !appxmanifest.contains_key(key)
+                        || values.iter().any(|(overridden, _)| *overridden == key)
+                    {
+                        appxmanifest[key] = value.clone();
+                    }
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Returns the `key = "value"` entry of `[package.metadata.appxmanifest]` that metadata
+/// placeholders expand to.
+///
+fn metadata_entry(key: &str, value: String) -> String {
+    format!("{} = {}", key, toml::Value::from(value))
+}
+
+/// Returns the value of the metadata `key` for a new package: The one in `values`, if
+/// given, the package name for display names, or `default` otherwise.
+///
+fn metadata_value(
+    key: &str,
+    default: &str,
+    values: &[(&'static str, String)],
+    package_name: Option<&str>,
+) -> String {
+    match values.iter().find(|(given, _)| *given == key) {
+        Some((_, value)) => value.clone(),
+        None => match (key, package_name) {
+            (PACKAGE_DISPLAY_NAME_KEY | PACKAGE_VISUAL_DISPLAY_NAME_KEY, Some(name)) => {
+                name.to_owned()
+            }
+            _ => default.to_owned(),
+        },
+    }
+}
+
+/// Returns the table `name` nested in `table`. If it doesn't exist, it is created at
+/// `position`.
+///
+fn table_mut<'a>(
+    table: &'a mut toml::Table,
+    name: &str,
+    position: usize,
+) -> anyhow::Result<&'a mut toml::Table> {
+    let item = table.entry(name);
+    if item.is_none() {
+        let mut table = toml::Table::new();
+        table.set_position(position);
+        *item = toml::Item::Table(table);
+    }
+    item.as_table_mut()
+        .ok_or_else(|| anyhow!("'{}' in Cargo.toml is not a table", name))
+}
+
+/// Returns the highest position of all tables nested in `table`.
+///
+fn last_table_position(table: &toml::Table) -> usize {
+    table
+        .iter()
+        .flat_map(|(_, item)| {
+            item.as_table().into_iter().chain(
+                item.as_array_of_tables()
+                    .into_iter()
+                    .flat_map(|tables| tables.iter()),
+            )
+        })
+        .map(|table| {
+            table
+                .position()
+                .unwrap_or(0)
+                .max(last_table_position(table))
+        })
+        .max()
+        .unwrap_or(0)
+}
+
+/// Inserts the dependency `name` into `dependencies`, unless it is listed already.
+///
+fn merge_dependency(dependencies: &mut toml::Table, name: &str, dependency: toml::Item) {
+    match dependencies.get(name) {
+        None => dependencies[name] = dependency,
+        Some(existing) if dependency_source(existing) == dependency_source(&dependency) => {}
+        Some(_) => warning(&format!(
+            "Keeping existing dependency '{}' in Cargo.toml",
+            name
+        )),
+    }
+}
+
+/// Returns the `[dependencies.windows]` table for `version` and `features`, placed at
+/// `position`.
+///
+fn windows_dependency(version: &str, features: &[String], position: usize) -> toml::Item {
+    let mut windows = toml::Table::new();
+    windows.set_position(position);
+    windows["version"] = toml::value(version);
+    let mut feature_list = toml::Array::default();
+    for feature in features {
+        let _ = feature_list.push(feature.as_str());
+    }
+    windows["features"] = toml::value(feature_list);
+
+    toml::Item::Table(windows)
+}
+
+/// Returns the version requirement or path of a dependency specification.
+///
+fn dependency_source(dependency: &toml::Item) -> Option<&str> {
+    dependency
+        .as_str()
+        .or_else(|| dependency["version"].as_str())
+        .or_else(|| dependency["path"].as_str())
+}
+
+#[derive(Debug, StructOpt)]
+pub(crate) enum Migrate {
+    #[structopt(
+        about = "Replaces the bindings crate with the cargo features of current windows-rs versions"
+    )]
+    WindowsRs(MigrateWindowsRs),
+}
+
+impl Migrate {
+    pub(crate) fn perform(&self) -> anyhow::Result<()> {
+        match self {
+            Migrate::WindowsRs(windows_rs) => windows_rs.perform(),
+        }
+    }
+}
+
+#[derive(Debug, StructOpt)]
+pub(crate) struct MigrateWindowsRs {
+    #[structopt(
+        parse(from_os_str),
+        default_value = ".",
+        help = "Root directory of the cargo package"
+    )]
+    path: PathBuf,
+    #[structopt(
+        long,
+        default_value = WINDOWS_RS_MIGRATION_VERSION,
+        help = "Version of the windows crate to migrate to"
+    )]
+    windows_rs: String,
+    #[structopt(long, help = "Print what would be done without doing it")]
+    dry_run: bool,
+    #[structopt(
+        long,
+        default_value = "human",
+        help = "Output format of --dry-run (human or json)"
+    )]
+    message_format: MessageFormat,
+}
+
+impl MigrateWindowsRs {
+    pub(crate) fn perform(&self) -> anyhow::Result<()> {
+        if !bindings::uses_features(&self.windows_rs)? {
+            bail!(
+                "windows-rs {} still requires a bindings crate, choose version 0.24 or later",
+                self.windows_rs
+            );
+        }
+
+        let package_root = PathBuf::from(&self.path);
+        let manifest_file = package_root.join("Cargo.toml");
+        let manifest = read_cargo_toml(&package_root)?;
+        let mut document: toml::Document = manifest
+            .parse()
+            .with_context(|| format!("Parsing {}", manifest_file.display()))?;
+        let dependencies = document["dependencies"]
+            .as_table_mut()
+            .ok_or_else(|| anyhow!("No [dependencies] table in {}", manifest_file.display()))?;
+        let bindings_dir = match dependencies["bindings"]["path"].as_str() {
+            // Drop `.` components (e.g. `./bindings`), so that the directory can be
+            // recognized while collecting sources
+            Some(path) => package_root.join(
+                Path::new(path)
+                    .components()
+                    .filter(|component| *component != Component::CurDir)
+                    .collect::<PathBuf>(),
+            ),
+            None => bail!(
+                "No 'bindings' path dependency in {}, nothing to migrate",
+                manifest_file.display()
+            ),
+        };
+        let build_rs_file = bindings_dir.join("build.rs");
+        let build_rs = fs::read_to_string(&build_rs_file)
+            .with_context(|| format!("Reading {}", build_rs_file.display()))?;
+
+        let mut source_files = Vec::new();
+        collect_sources(&package_root, &bindings_dir, &mut source_files)?;
+        let mut sources = Vec::new();
+        for file in source_files {
+            let source =
+                fs::read_to_string(&file).with_context(|| format!("Reading {}", file.display()))?;
+            sources.push((file, source));
+        }
+        let features = bindings::features(
+            &build_rs,
+            &sources
+                .iter()
+                .map(|(_, source)| source.as_str())
+                .collect::<Vec<_>>(),
+        );
+
+        let mut plan = Plan::new();
+        let mut follow_ups = Vec::new();
+        for (file, source) in &sources {
+            let ported = bindings::port_source(source);
+            follow_ups.extend(
+                bindings::follow_ups(&ported)
+                    .into_iter()
+                    .map(|follow_up| format!("{}: {}", file.display(), follow_up)),
+            );
+            if ported != *source {
+                plan.update(file.clone(), ported);
+            }
+        }
+
+        // Replace both dependencies with a `[dependencies.windows]` table following the
+        // `[dependencies]` table
+        let position = dependencies.position().unwrap_or(0);
+        dependencies.remove("bindings");
+        dependencies["windows"] = windows_dependency(&self.windows_rs, &features, position);
+        plan.update_manifest(
+            manifest_file,
+            &manifest,
+            document.to_string_in_original_order(),
         )?;
-        write_file(
-            &package_root,
-            Some(&[BUILD_DIR]),
-            SHARED_RS_FILENAME,
-            SHARED_RS,
+        plan.remove(bindings_dir);
+
+        if self.dry_run {
+            plan.print(self.message_format);
+        } else {
+            plan.apply()?;
+        }
+        for follow_up in &follow_ups {
+            warning(follow_up);
+        }
+        if self.dry_run {
+            return Ok(());
+        }
+        if follow_ups.is_empty() {
+            status(
+                "Migrated",
+                &format!("{} to windows {}", package_root.display(), self.windows_rs),
+            );
+        } else {
+            warning(&format!(
+                "Migrated {} to windows {}, but {} construct(s) need porting by hand before it builds",
+                package_root.display(),
+                self.windows_rs,
+                follow_ups.len()
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+/// Collects the Rust source files of the package at `dir`, skipping `skip_dir`, the target
+/// directory, and hidden directories.
+///
+fn collect_sources(dir: &Path, skip_dir: &Path, files: &mut Vec<PathBuf>) -> anyhow::Result<()> {
+    let mut entries = fs::read_dir(dir)
+        .with_context(|| format!("Reading directory {}", dir.display()))?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<Result<Vec<_>, _>>()?;
+    entries.sort();
+    for path in entries {
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        if path.is_dir() {
+            if path != skip_dir && name != "target" && !name.starts_with('.') {
+                collect_sources(&path, skip_dir, files)?;
+            }
+        } else if path.extension() == Some(OsStr::new("rs")) {
+            files.push(path);
+        }
+    }
+
+    Ok(())
+}
+
+/// Selects the cargo profile whose artifacts a command operates on.
+///
+#[derive(Debug, StructOpt)]
+pub(crate) struct Profile {
+    #[structopt(
+        long,
+        conflicts_with = "profile",
+        help = "Use artifacts built with the release profile (same as --profile release)"
+    )]
+    release: bool,
+    #[structopt(
+        long,
+        value_name = "name",
+        help = "Use artifacts built with the given cargo profile [default: dev]"
+    )]
+    profile: Option<String>,
+}
+
+impl Profile {
+    fn name(&self) -> &str {
+        match &self.profile {
+            Some(profile) => profile,
+            None if self.release => "release",
+            None => "dev",
+        }
+    }
+}
+
+#[derive(Debug, StructOpt)]
+pub(crate) struct Pack {
+    #[structopt(
+        long,
+        parse(from_os_str),
+        help = "Path to the FileMapping.ini generated by the build [default: the one in the artifact directory]"
+    )]
+    mapping_file: Option<PathBuf>,
+    #[structopt(
+        long,
+        help = "Target triple to package [default: build.target from .cargo/config.toml]"
+    )]
+    target: Option<String>,
+    #[structopt(flatten)]
+    profile: Profile,
+    #[structopt(
+        long,
+        parse(from_os_str),
+        help = "Directory to write the package to [default: the directory of the mapping file]"
+    )]
+    out_dir: Option<PathBuf>,
+    #[structopt(long, default_value = "appx", help = "Package format (appx or msix)")]
+    format: Format,
+    #[structopt(long, help = "Store files without compressing them")]
+    no_compression: bool,
+    #[structopt(
+        long,
+        default_value = "sha256",
+        help = "Hash algorithm for the block map (sha256, sha384, or sha512); must match the one used for signing"
+    )]
+    hash_algorithm: HashAlgorithm,
+}
+
+impl Pack {
+    pub(crate) fn perform(&self) -> anyhow::Result<()> {
+        let mapping_file = match &self.mapping_file {
+            Some(mapping_file) => mapping_file.clone(),
+            None => {
+                let package_root = artifacts::find_package_root()?;
+                let mut mapping_file = artifacts::artifact_dir(
+                    &package_root,
+                    self.target.as_deref(),
+                    self.profile.name(),
+                )?;
+                mapping_file.push(FILE_MAPPINGS_TEMPLATE_FILENAME);
+                mapping_file
+            }
+        };
+        let files = appx::read_mapping_file(&mapping_file)?;
+
+        // Name the package after the identity declared in the manifest
+        let manifest = files
+            .iter()
+            .find(|file| file.name.eq_ignore_ascii_case(APPX_MANIFEST_NAME))
+            .ok_or_else(|| {
+                anyhow!(
+                    "{} doesn't map {}",
+                    mapping_file.display(),
+                    APPX_MANIFEST_NAME
+                )
+            })?;
+        let manifest = fs::read_to_string(&manifest.source)
+            .with_context(|| format!("Reading {}", manifest.source.display()))?;
+        let identity = PackageIdentity::from_manifest(&manifest)?;
+
+        let mut package = match &self.out_dir {
+            Some(out_dir) => out_dir.clone(),
+            None => mapping_file
+                .parent()
+                .map(Path::to_path_buf)
+                .unwrap_or_default(),
+        };
+        fs::create_dir_all(&package)?;
+        package.push(identity.package_file_name(self.format));
+
+        status("Packaging", &package.display());
+        appx::write_package(&package, &files, !self.no_compression, self.hash_algorithm)?;
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, StructOpt)]
+pub(crate) struct Bundle {
+    #[structopt(
+        parse(from_os_str),
+        help = "Packages to bundle [default: the packages in the artifact directories of all targets]"
+    )]
+    packages: Vec<PathBuf>,
+    #[structopt(flatten)]
+    profile: Profile,
+    #[structopt(
+        long,
+        help = "Identity version of the packages to bundle [default: the only version found]"
+    )]
+    version: Option<String>,
+    #[structopt(
+        long,
+        parse(from_os_str),
+        help = "Directory to write the bundle to [default: the target directory]"
+    )]
+    out_dir: Option<PathBuf>,
+    #[structopt(long, default_value = "appx", help = "Package format (appx or msix)")]
+    format: Format,
+    #[structopt(
+        long,
+        default_value = "sha256",
+        help = "Hash algorithm for the block map (sha256, sha384, or sha512); must match the one used for signing"
+    )]
+    hash_algorithm: HashAlgorithm,
+}
+
+impl Bundle {
+    pub(crate) fn perform(&self) -> anyhow::Result<()> {
+        let mut packages = Vec::new();
+        let out_dir = if self.packages.is_empty() {
+            // Collect packages from the artifact directories of all targets
+            let package_root = artifacts::find_package_root()?;
+            for dir in artifacts::all_artifact_dirs(&package_root, self.profile.name())? {
+                for entry in fs::read_dir(&dir)? {
+                    let path = entry?.path();
+                    let extension = path.extension().and_then(OsStr::to_str).unwrap_or_default();
+                    if path.is_file()
+                        && extension.eq_ignore_ascii_case(self.format.package_extension())
+                    {
+                        packages.push(BundlePackage::read(&path)?);
+                    }
+                }
+            }
+            artifacts::target_dir(&package_root)?
+        } else {
+            for path in &self.packages {
+                packages.push(BundlePackage::read(path)?);
+            }
+            PathBuf::from(".")
+        };
+
+        // Restrict packages to a single identity version
+        let version = match &self.version {
+            Some(version) => version.clone(),
+            None => {
+                let mut versions: Vec<_> = packages
+                    .iter()
+                    .map(|package| package.identity.version.clone())
+                    .collect();
+                versions.sort();
+                versions.dedup();
+                match versions.len() {
+                    0 => bail!("No {} packages found", self.format),
+                    1 => versions.remove(0),
+                    _ => bail!(
+                        "Found packages for multiple versions ({}); select one using --version",
+                        versions.join(", ")
+                    ),
+                }
+            }
+        };
+        packages.retain(|package| package.identity.version == version);
+        if packages.is_empty() {
+            bail!("No {} packages found for version {}", self.format, version);
+        }
+        bundle::validate_packages(&mut packages)?;
+
+        let mut path = self.out_dir.clone().unwrap_or(out_dir);
+        fs::create_dir_all(&path)?;
+        path.push(bundle::bundle_file_name(&packages, self.format));
+
+        for package in &packages {
+            status("Adding", &package.path.display());
+        }
+        status("Bundling", &path.display());
+        bundle::write_bundle(&path, &packages, self.hash_algorithm)?;
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, StructOpt)]
+pub(crate) struct Sym {
+    #[structopt(
+        long,
+        help = "Target triple to collect symbols for [default: build.target from .cargo/config.toml]"
+    )]
+    target: Option<String>,
+    #[structopt(flatten)]
+    profile: Profile,
+    #[structopt(
+        long,
+        parse(from_os_str),
+        help = "Directory to write the symbol archive to [default: the artifact directory]"
+    )]
+    out_dir: Option<PathBuf>,
+}
+
+impl Sym {
+    pub(crate) fn perform(&self) -> anyhow::Result<()> {
+        let package_root = artifacts::find_package_root()?;
+        let artifact_dir =
+            artifacts::artifact_dir(&package_root, self.target.as_deref(), self.profile.name())?;
+
+        // Name the archive after the identity declared in the generated manifest
+        let manifest = artifact_dir.join(APPX_MANIFEST_NAME);
+        let manifest = fs::read_to_string(&manifest)
+            .with_context(|| format!("Reading {}", manifest.display()))?;
+        let identity = PackageIdentity::from_manifest(&manifest)?;
+
+        let mut pdbs = Vec::new();
+        for entry in fs::read_dir(&artifact_dir)? {
+            let path = entry?.path();
+            let extension = path.extension().and_then(OsStr::to_str).unwrap_or_default();
+            if path.is_file() && extension.eq_ignore_ascii_case("pdb") {
+                pdbs.push(path);
+            }
+        }
+        if pdbs.is_empty() {
+            bail!("No PDB files found in {}", artifact_dir.display());
+        }
+        pdbs.sort();
+
+        let mut path = self.out_dir.clone().unwrap_or(artifact_dir);
+        fs::create_dir_all(&path)?;
+        path.push(identity.symbol_file_name());
+
+        // Write to a temporary file first, so that a failure doesn't leave a partial
+        // archive behind
+        let temp_path = path.with_extension("partial");
+        let write_archive = || -> anyhow::Result<()> {
+            let file = File::create(&temp_path)
+                .with_context(|| format!("Creating {}", temp_path.display()))?;
+            let mut zip = ZipWriter::new(BufWriter::new(file));
+            for pdb in &pdbs {
+                status("Stripping", &pdb.display());
+                let data = fs::read(pdb).with_context(|| format!("Reading {}", pdb.display()))?;
+                let stripped = pdb::strip_private_symbols(&data)
+                    .with_context(|| format!("Stripping private symbols from {}", pdb.display()))?;
+                let name = pdb.file_name().unwrap().to_string_lossy();
+                zip.write_file(&appx::part_name(&name), &stripped, true)?;
+            }
+            zip.finish()?;
+            fs::rename(&temp_path, &path).with_context(|| format!("Writing {}", path.display()))?;
+            Ok(())
+        };
+        if let Err(e) = write_archive() {
+            let _ = fs::remove_file(&temp_path);
+            return Err(e);
+        }
+        status("Finished", &path.display());
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, StructOpt)]
+pub(crate) struct Upload {
+    #[structopt(flatten)]
+    profile: Profile,
+    #[structopt(
+        long,
+        help = "Identity version of the bundle to upload [default: the only version found]"
+    )]
+    version: Option<String>,
+    #[structopt(
+        long,
+        parse(from_os_str),
+        help = "Directory to write the upload file to [default: the target directory]"
+    )]
+    out_dir: Option<PathBuf>,
+    #[structopt(long, default_value = "appx", help = "Package format (appx or msix)")]
+    format: Format,
+}
+
+impl Upload {
+    pub(crate) fn perform(&self) -> anyhow::Result<()> {
+        let package_root = artifacts::find_package_root()?;
+        let target_dir = artifacts::target_dir(&package_root)?;
+
+        // Bundles are written to the target directory, symbol archives to the artifact
+        // directories of the respective targets
+        let mut search_dirs = artifacts::all_artifact_dirs(&package_root, self.profile.name())?;
+        search_dirs.insert(0, target_dir.clone());
+
+        let mut bundles = Vec::new();
+        for dir in &search_dirs {
+            for entry in fs::read_dir(dir)? {
+                let path = entry?.path();
+                let extension = path.extension().and_then(OsStr::to_str).unwrap_or_default();
+                if path.is_file() && extension.eq_ignore_ascii_case(self.format.bundle_extension())
+                {
+                    bundles.push(BundleInfo::read(&path)?);
+                }
+            }
+        }
+        if let Some(version) = &self.version {
+            bundles.retain(|bundle| &bundle.version == version);
+        }
+        let bundle = match bundles.len() {
+            0 => match &self.version {
+                Some(version) => bail!(
+                    "No {} found for version {}",
+                    self.format.bundle_extension(),
+                    version
+                ),
+                None => bail!("No {} found", self.format.bundle_extension()),
+            },
+            1 => bundles.remove(0),
+            _ => {
+                let paths: Vec<_> = bundles
+                    .iter()
+                    .map(|bundle| bundle.path.display().to_string())
+                    .collect();
+                bail!(
+                    "Found multiple bundles ({}); select one using --version",
+                    paths.join(", ")
+                )
+            }
+        };
+
+        // Every bundled architecture needs its symbols
+        let mut symbols = Vec::new();
+        for arch in &bundle.archs {
+            let file_name = format!("{}_{}_{}.appxsym", bundle.name, bundle.version, arch);
+            let path = search_dirs
+                .iter()
+                .map(|dir| dir.join(&file_name))
+                .find(|path| path.is_file())
+                .ok_or_else(|| {
+                    anyhow!(
+                        "No symbol archive {} found for the {} architecture (create one using \
+                         `cargo uwp sym`)",
+                        file_name,
+                        arch
+                    )
+                })?;
+            symbols.push(path);
+        }
+
+        let mut path = self.out_dir.clone().unwrap_or(target_dir);
+        fs::create_dir_all(&path)?;
+        path.push(upload::upload_file_name(&bundle, self.format));
+
+        for file in std::iter::once(&bundle.path).chain(&symbols) {
+            status("Adding", &file.display());
+        }
+        status("Archiving", &path.display());
+        upload::write_upload(&path, &bundle, &symbols)?;
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, StructOpt)]
+pub(crate) struct Sign {
+    #[structopt(parse(from_os_str), help = "Package or bundle to sign")]
+    package: PathBuf,
+    #[structopt(
+        long,
+        parse(from_os_str),
+        help = "PFX file holding the signing certificate and its private key"
+    )]
+    certificate: PathBuf,
+    #[structopt(
+        long,
+        env = CERTIFICATE_PASSWORD_ENV,
+        hide_env_values = true,
+        help = "Password protecting the PFX file"
+    )]
+    password: Option<String>,
+    #[structopt(
+        long,
+        help = "Hash algorithm (sha256, sha384, or sha512) [default: the one the block map was created with]"
+    )]
+    hash_algorithm: Option<HashAlgorithm>,
+}
+
+impl Sign {
+    pub(crate) fn perform(&self) -> anyhow::Result<()> {
+        let package_root = artifacts::find_package_root()?;
+        let publisher =
+            artifacts::manifest_metadata(&package_root, PACKAGE_IDENTITY_PUBLISHER_KEY)?;
+
+        let certificate = SigningCertificate::from_pfx(
+            &self.certificate,
+            self.password.as_deref().unwrap_or_default(),
         )?;
+        // Windows refuses to install packages whose publisher differs from the signer
+        if !certificate.matches_publisher(&publisher) {
+            bail!(
+                "The certificate subject '{}' doesn't match the {} '{}' in Cargo.toml",
+                certificate.subject(),
+                PACKAGE_IDENTITY_PUBLISHER_KEY,
+                publisher
+            );
+        }
+
+        status("Signing", &self.package.display());
+        sign::sign_package(&self.package, &certificate, self.hash_algorithm)?;
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, StructOpt)]
+pub(crate) enum Cert {
+    #[structopt(about = "Creates a self-signed code signing certificate for side-loading")]
+    New(CertNew),
+}
+
+impl Cert {
+    pub(crate) fn perform(&self) -> anyhow::Result<()> {
+        match self {
+            Cert::New(new) => new.perform(),
+        }
+    }
+}
+
+#[derive(Debug, StructOpt)]
+pub(crate) struct CertNew {
+    #[structopt(
+        long,
+        default_value = "365",
+        help = "Number of days the certificate is valid for"
+    )]
+    validity_days: u32,
+    #[structopt(
+        long,
+        parse(from_os_str),
+        help = "Directory to write the certificate to [default: the package root]"
+    )]
+    out_dir: Option<PathBuf>,
+    #[structopt(long, help = "Overwrite existing certificate files")]
+    force: bool,
+}
+
+impl CertNew {
+    pub(crate) fn perform(&self) -> anyhow::Result<()> {
+        if self.validity_days == 0 {
+            bail!("The validity period must be at least one day");
+        }
+
+        let package_root = artifacts::find_package_root()?;
+        let name = artifacts::package_name(&package_root)?;
+        let publisher =
+            artifacts::manifest_metadata(&package_root, PACKAGE_IDENTITY_PUBLISHER_KEY)?;
+
+        let out_dir = self.out_dir.clone().unwrap_or(package_root);
+        let pfx_path = out_dir.join(format!("{}_TemporaryKey.pfx", name));
+        let cer_path = pfx_path.with_extension("cer");
+        if !self.force {
+            if let Some(existing) = [&pfx_path, &cer_path].iter().find(|path| path.exists()) {
+                bail!(
+                    "{} already exists; use --force to overwrite it",
+                    existing.display()
+                );
+            }
+        }
+
+        let password = match env::var(CERTIFICATE_PASSWORD_ENV) {
+            Ok(password) => password,
+            Err(_) => read_new_password(&pfx_path)?,
+        };
+
+        status("Generating", &format!("certificate for {}", publisher));
+        let validity = Duration::from_secs(u64::from(self.validity_days) * 24 * 60 * 60);
+        let certificate = SelfSignedCertificate::new(&publisher, validity)?;
+
+        fs::create_dir_all(&out_dir)?;
+        fs::write(&pfx_path, certificate.to_pfx(&name, &password)?)
+            .with_context(|| format!("Writing {}", pfx_path.display()))?;
+        fs::write(&cer_path, certificate.to_der())
+            .with_context(|| format!("Writing {}", cer_path.display()))?;
+        status("Created", &pfx_path.display());
+        status("Created", &cer_path.display());
 
         Ok(())
     }
 }
 
-/// Writes binary/string content into file. The destination directory is created starting
-/// from `base_dir` and subsequently appending all `sub_dir` parts, if any.
+/// Reads the password protecting a new PFX file from stdin. When run interactively, the
+/// password is entered twice, without echoing it.
 ///
-/// If the destination directory (or a parent) doesn't exist it is created.
+fn read_new_password(pfx_path: &Path) -> anyhow::Result<String> {
+    if !io::stdin().is_terminal() {
+        let mut password = String::new();
+        io::stdin()
+            .read_line(&mut password)
+            .context("Reading the password from stdin")?;
+        return Ok(password.trim_end_matches(['\r', '\n']).to_owned());
+    }
+
+    let terminal = Term::stderr();
+    terminal.write_str(&format!("Password for {}: ", pfx_path.display()))?;
+    let password = terminal.read_secure_line()?;
+    terminal.write_str("Confirm password: ")?;
+    if terminal.read_secure_line()? != password {
+        bail!("The passwords don't match");
+    }
+
+    Ok(password)
+}
+
+/// Prints a status message, formatted the same way cargo does.
 ///
-fn write_file<'a>(
-    base_dir: &Path,
-    sub_dir: Option<&'a [&'a str]>,
-    dest_name: impl AsRef<OsStr>,
-    contents: impl AsRef<[u8]>,
-) -> anyhow::Result<()> {
-    let mut dest_dir: PathBuf = base_dir.into();
+pub(crate) fn status(action: &str, message: &dyn std::fmt::Display) {
+    println!("{:>12} {}", console::style(action).green().bold(), message);
+}
+
+/// Prints a warning, formatted the same way cargo does.
+///
+fn warning(message: &str) {
+    eprintln!("{}: {}", console::style("warning").yellow().bold(), message);
+}
 
-    // Construct destination directory
-    if let Some(sub_dirs) = sub_dir {
-        for sub_dir in sub_dirs {
-            dest_dir.push(sub_dir);
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shared::PACKAGE_PUBLISHER_DISPLAY_NAME_DEFAULT;
+
+    const MANIFEST: &str =
+        "[package]\nname = \"app\"\nversion = \"0.1.0\"\nedition = \"2018\"\n\n[dependencies]\n";
+
+    /// Returns an empty directory private to the calling test.
+    ///
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("cargo-uwp-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("src")).unwrap();
+        dir
+    }
+
+    /// Generates a package from `options` in a new directory, returning its path.
+    ///
+    fn generate(name: &str, options: &PackageOptions) -> PathBuf {
+        let package_root = temp_dir(name);
+        let mut plan = Plan::new();
+        plan.write(
+            &package_root,
+            Some(&["src"]),
+            "main.rs",
+            options.src_main_rs(),
+        );
+        generate_package(&package_root, MANIFEST, options, &mut plan).unwrap();
+        plan.apply().unwrap();
+        package_root
+    }
+
+    #[test]
+    fn new_with_windows_features() {
+        let options =
+            PackageOptions::new(find_starter("core").unwrap(), "0.48.0", None, Vec::new()).unwrap();
+        let package_root = generate("features", &options);
+
+        let manifest: toml::Document = fs::read_to_string(package_root.join("Cargo.toml"))
+            .unwrap()
+            .parse()
+            .unwrap();
+        let windows = &manifest["dependencies"]["windows"];
+        assert_eq!(windows["version"].as_str(), Some("0.48.0"));
+        let features = windows["features"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .filter_map(|feature| feature.as_str())
+            .collect::<Vec<_>>();
+        for feature in [
+            "implement",
+            "ApplicationModel_Core",
+            "UI_Core",
+            "Win32_Foundation",
+            "Win32_System_Com",
+        ] {
+            assert!(features.contains(&feature), "missing feature {}", feature);
         }
+        assert!(manifest["dependencies"]["bindings"].is_none());
+        assert!(!package_root.join(BINDINGS_CRATE_PATH).exists());
+
+        let main_rs = fs::read_to_string(package_root.join("src").join("main.rs")).unwrap();
+        for legacy in ["bindings::", "extend", "null_mut", "().new()", "try_from"] {
+            assert!(!main_rs.contains(legacy), "main.rs contains {}", legacy);
+        }
+        assert!(main_rs.contains("impl IFrameworkView_Impl for MyView"));
+        assert!(main_rs.contains("CoInitializeEx(None, COINIT_MULTITHREADED)"));
+
+        fs::remove_dir_all(package_root).unwrap();
     }
 
-    // Ensure the directory exists
-    if !dest_dir.exists() {
-        fs::create_dir_all(&dest_dir)?;
+    #[test]
+    fn new_with_bindings_crate() {
+        let options = PackageOptions::new(
+            find_starter("xaml").unwrap(),
+            WINDOWS_RS_VERSION,
+            None,
+            Vec::new(),
+        )
+        .unwrap();
+        let package_root = generate("bindings", &options);
+
+        assert!(package_root
+            .join(BINDINGS_CRATE_PATH)
+            .join("build.rs")
+            .is_file());
+        let manifest = fs::read_to_string(package_root.join("Cargo.toml")).unwrap();
+        assert!(manifest.contains("bindings = { path = \"./bindings\" }"));
+
+        fs::remove_dir_all(package_root).unwrap();
     }
 
-    let mut dest_file = dest_dir;
-    dest_file.push(dest_name.as_ref());
+    #[test]
+    fn new_with_template_dir() {
+        let template_dir = temp_dir("template-dir");
+        fs::write(
+            template_dir.join("Cargo.toml"),
+            "[dependencies]\nlog = \"0.4\"\n\n[package.metadata.appxmanifest]\n\
+             $package-identity-name$\ncapabilities = [\"internetClient\"]\n",
+        )
+        .unwrap();
+        fs::write(
+            template_dir.join("src").join("main.rs"),
+            "// $package-properties-publisherdisplayname$\nfn main() {}\n",
+        )
+        .unwrap();
+        let options = PackageOptions::new(
+            find_starter("xaml").unwrap(),
+            WINDOWS_RS_VERSION,
+            Some(template_dir.clone()),
+            vec![(PACKAGE_IDENTITY_NAME_KEY, "Contoso.App".to_owned())],
+        )
+        .unwrap();
+        let package_root = generate("template-package", &options);
 
-    // Write out contents
-    fs::write(&dest_file, contents)?;
+        let manifest: toml::Document = fs::read_to_string(package_root.join("Cargo.toml"))
+            .unwrap()
+            .parse()
+            .unwrap();
+        assert_eq!(manifest["package"]["name"].as_str(), Some("app"));
+        assert_eq!(manifest["dependencies"]["log"].as_str(), Some("0.4"));
+        assert!(manifest["dependencies"]["bindings"].is_table_like());
+        let appxmanifest = &manifest["package"]["metadata"]["appxmanifest"];
+        assert_eq!(
+            appxmanifest[PACKAGE_IDENTITY_NAME_KEY].as_str(),
+            Some("Contoso.App")
+        );
+        assert_eq!(appxmanifest["capabilities"].as_array().unwrap().len(), 1);
+        assert_eq!(
+            appxmanifest[PACKAGE_VISUAL_DISPLAY_NAME_KEY].as_str(),
+            Some("app")
+        );
 
-    Ok(())
+        let main_rs = fs::read_to_string(package_root.join("src").join("main.rs")).unwrap();
+        assert!(main_rs.starts_with(&format!(
+            "// {} = \"{}\"\n",
+            PACKAGE_PUBLISHER_DISPLAY_NAME_KEY, PACKAGE_PUBLISHER_DISPLAY_NAME_DEFAULT
+        )));
+
+        fs::remove_dir_all(template_dir).unwrap();
+        fs::remove_dir_all(package_root).unwrap();
+    }
+
+    #[test]
+    fn migrate_windows_rs() {
+        let options = PackageOptions::new(
+            find_starter("xaml").unwrap(),
+            WINDOWS_RS_VERSION,
+            None,
+            Vec::new(),
+        )
+        .unwrap();
+        let package_root = generate("migrate", &options);
+        MigrateWindowsRs {
+            path: package_root.clone(),
+            windows_rs: WINDOWS_RS_MIGRATION_VERSION.to_owned(),
+            dry_run: false,
+            message_format: MessageFormat::Human,
+        }
+        .perform()
+        .unwrap();
+
+        let manifest: toml::Document = fs::read_to_string(package_root.join("Cargo.toml"))
+            .unwrap()
+            .parse()
+            .unwrap();
+        let windows = &manifest["dependencies"]["windows"];
+        assert_eq!(
+            windows["version"].as_str(),
+            Some(WINDOWS_RS_MIGRATION_VERSION)
+        );
+        let features = windows["features"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .filter_map(|feature| feature.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            features,
+            [
+                "ApplicationModel_Activation",
+                "Foundation",
+                "UI_Xaml",
+                "UI_Xaml_Controls",
+                "Win32_Foundation",
+                "Win32_System_Com",
+                "implement",
+            ]
+        );
+        assert!(manifest["dependencies"]["bindings"].is_none());
+        assert!(!package_root.join(BINDINGS_CRATE_PATH).exists());
+
+        let main_rs = fs::read_to_string(package_root.join("src").join("main.rs")).unwrap();
+        assert!(!main_rs.contains("bindings::"));
+        let follow_ups = bindings::follow_ups(&main_rs);
+        assert_eq!(follow_ups.len(), 4, "{:?}", follow_ups);
+        assert!(follow_ups[0].contains("extend"));
+
+        fs::remove_dir_all(package_root).unwrap();
+    }
+
+    #[test]
+    fn migrate_follow_ups() {
+        let core = find_starter("core").unwrap();
+        let ported = bindings::port_source(&String::from_utf8_lossy(core.src_main_rs));
+        let follow_ups = bindings::follow_ups(&ported);
+        assert_eq!(follow_ups.len(), 2, "{:?}", follow_ups);
+        assert!(follow_ups[0].contains("_Impl for"));
+
+        let features = core.features.as_ref().unwrap();
+        assert!(bindings::follow_ups(&String::from_utf8_lossy(features.src_main_rs)).is_empty());
+    }
+
+    #[test]
+    fn rejects_unsupported_windows_versions() {
+        let core = find_starter("core").unwrap();
+        let xaml = find_starter("xaml").unwrap();
+        assert!(PackageOptions::new(xaml, "0.48.0", None, Vec::new()).is_err());
+        assert!(PackageOptions::new(core, "0.30.0", None, Vec::new()).is_err());
+        assert!(PackageOptions::new(core, "0.52.0", None, Vec::new()).is_err());
+        assert!(PackageOptions::new(core, "0.51.1", None, Vec::new()).is_ok());
+        assert!(PackageOptions::new(core, "0.23.0", None, Vec::new()).is_ok());
+    }
 }
@@ -0,0 +1,614 @@
+//! Implements stripping of private symbols from PDB files.
+//!
+//! A PDB is an MSF ("multi-stream format") container, a tiny file system made up of
+//! fixed-size blocks, holding a list of streams. Stripping produces the equivalent of
+//! `pdbcopy /p`: The PDB info stream (whose GUID and age tie the PDB to the executable
+//! image), section contributions, section headers, and public symbols are retained. Type
+//! information, global symbols, and per-module symbols and line information are dropped.
+//!
+use std::convert::TryInto;
+
+use anyhow::{bail, Context};
+
+const MSF_MAGIC: &[u8; 32] = b"Microsoft C/C++ MSF 7.00\r\n\x1aDS\0\0\0";
+const SUPER_BLOCK_SIZE: usize = 56;
+const NIL_STREAM_SIZE: u32 = u32::MAX;
+
+const TPI_STREAM: usize = 2;
+const DBI_STREAM: usize = 3;
+const IPI_STREAM: usize = 4;
+const INVALID_STREAM_INDEX: u16 = u16::MAX;
+
+const DBI_HEADER_SIZE: usize = 64;
+const DBI_FLAG_STRIPPED: u16 = 0x2;
+const MODULE_INFO_HEADER_SIZE: usize = 64;
+
+const TPI_VERSION_V80: u32 = 20_040_203;
+const TPI_HEADER_SIZE: u32 = 56;
+const TPI_FIRST_TYPE_INDEX: u32 = 0x1000;
+
+const S_PUB32: u16 = 0x110e;
+
+const GSI_HASH_SIGNATURE: u32 = u32::MAX;
+const GSI_HASH_VERSION: u32 = 0xeffe_0000 + 19_990_810;
+const GSI_HASH_BUCKETS: usize = 4096;
+const GSI_BITMAP_WORDS: usize = (GSI_HASH_BUCKETS + 32) / 32;
+/// Size of an in-memory hash record on 32-bit platforms, which is what bucket offsets are
+/// expressed in.
+const GSI_HASH_RECORD_SIZE_IN_MEMORY: u32 = 12;
+const PSI_HEADER_SIZE: usize = 28;
+
+/// Reads the PDB in `data`, and returns a copy with private symbols removed.
+///
+pub(crate) fn strip_private_symbols(data: &[u8]) -> anyhow::Result<Vec<u8>> {
+    let (block_size, mut streams) = read_msf(data)?;
+
+    // Patch up the DBI stream, and find the streams it references
+    let mut dbi = streams
+        .get_mut(DBI_STREAM)
+        .and_then(Option::take)
+        .context("PDB file is missing the DBI stream")?;
+    if dbi.len() < DBI_HEADER_SIZE || read_u32(&dbi, 0)? != u32::MAX {
+        bail!("Unsupported DBI stream version");
+    }
+    let globals_stream = read_u16(&dbi, 12)?;
+    let publics_stream = read_u16(&dbi, 16)?;
+    let symbol_records_stream = read_u16(&dbi, 20)?;
+    let module_info_size = read_u32(&dbi, 24)? as usize;
+
+    // Detach module symbol streams, which hold all private symbols and line information
+    let mut pos = DBI_HEADER_SIZE;
+    let end = DBI_HEADER_SIZE + module_info_size;
+    if end > dbi.len() {
+        bail!("DBI module information exceeds stream");
+    }
+    while pos + MODULE_INFO_HEADER_SIZE <= end {
+        let module_stream = read_u16(&dbi, pos + 34)?;
+        remove_stream(&mut streams, module_stream);
+        dbi[pos + 34..pos + 36].copy_from_slice(&INVALID_STREAM_INDEX.to_le_bytes());
+        for byte in &mut dbi[pos + 36..pos + 48] {
+            *byte = 0; // symbol, C11 line, and C13 line information sizes
+        }
+
+        // Skip over the module name and object file name
+        let mut names_end = pos + MODULE_INFO_HEADER_SIZE;
+        for _ in 0..2 {
+            names_end += dbi[names_end..end]
+                .iter()
+                .position(|&b| b == 0)
+                .context("Invalid module information in DBI stream")?
+                + 1;
+        }
+        pos = (names_end + 3) & !3;
+    }
+    let flags = read_u16(&dbi, 56)? | DBI_FLAG_STRIPPED;
+    dbi[56..58].copy_from_slice(&flags.to_le_bytes());
+    streams[DBI_STREAM] = Some(dbi);
+
+    // Replace type information with empty type streams
+    for index in [TPI_STREAM, IPI_STREAM] {
+        if let Some(Some(stream)) = streams.get(index) {
+            if stream.len() >= TPI_HEADER_SIZE as usize {
+                let hash_stream = read_u16(stream, 20)?;
+                let hash_aux_stream = read_u16(stream, 22)?;
+                remove_stream(&mut streams, hash_stream);
+                remove_stream(&mut streams, hash_aux_stream);
+            }
+            streams[index] = Some(empty_type_stream());
+        }
+    }
+
+    // Keep only public symbols, and rebuild the hash tables referencing them
+    let symbol_records = stream(&streams, symbol_records_stream)
+        .context("PDB file is missing the symbol records stream")?;
+    let publics = public_symbols(symbol_records)?;
+    let (symbol_records, offsets) = serialize_public_symbols(&publics);
+
+    let old_publics =
+        stream(&streams, publics_stream).context("PDB file is missing the publics stream")?;
+    let publics_hash = gsi_hash(&publics, &offsets);
+    let address_map = address_map(&publics, &offsets);
+    let mut new_publics = Vec::new();
+    new_publics.extend_from_slice(&(publics_hash.len() as u32).to_le_bytes());
+    new_publics.extend_from_slice(&(address_map.len() as u32).to_le_bytes());
+    new_publics.extend_from_slice(slice(old_publics, 8, PSI_HEADER_SIZE - 8)?);
+    new_publics.extend_from_slice(&publics_hash);
+    new_publics.extend_from_slice(&address_map);
+    // Thunk and section maps follow the address map; these are retained
+    let old_tail =
+        PSI_HEADER_SIZE + read_u32(old_publics, 0)? as usize + read_u32(old_publics, 4)? as usize;
+    new_publics.extend_from_slice(old_publics.get(old_tail..).unwrap_or_default());
+
+    streams[symbol_records_stream as usize] = Some(symbol_records);
+    streams[publics_stream as usize] = Some(new_publics);
+    if let Some(globals) = streams.get_mut(globals_stream as usize) {
+        *globals = Some(gsi_hash(&[], &[]));
+    }
+
+    write_msf(block_size, &streams)
+}
+
+/// An `S_PUB32` symbol record.
+///
+struct PublicSymbol<'a> {
+    flags: u32,
+    offset: u32,
+    segment: u16,
+    name: &'a [u8],
+}
+
+fn public_symbols(records: &[u8]) -> anyhow::Result<Vec<PublicSymbol<'_>>> {
+    let mut publics = Vec::new();
+    let mut pos = 0;
+    while pos + 4 <= records.len() {
+        let len = read_u16(records, pos)? as usize;
+        let kind = read_u16(records, pos + 2)?;
+        let record = slice(records, pos + 2, len)?;
+        if kind == S_PUB32 && record.len() >= 12 {
+            let name = &record[12..];
+            let name = &name[..name.iter().position(|&b| b == 0).unwrap_or(name.len())];
+            publics.push(PublicSymbol {
+                flags: read_u32(record, 2)?,
+                offset: read_u32(record, 6)?,
+                segment: read_u16(record, 10)?,
+                name,
+            });
+        }
+        pos += 2 + len;
+    }
+
+    Ok(publics)
+}
+
+/// Serializes `publics` into a symbol records stream. Returns the stream, alongside the
+/// offset of each record.
+///
+fn serialize_public_symbols(publics: &[PublicSymbol<'_>]) -> (Vec<u8>, Vec<u32>) {
+    let mut stream = Vec::new();
+    let mut offsets = Vec::with_capacity(publics.len());
+    for public in publics {
+        offsets.push(stream.len() as u32);
+        let size = (2 + 2 + 4 + 4 + 2 + public.name.len() + 1 + 3) & !3;
+        stream.extend_from_slice(&((size - 2) as u16).to_le_bytes());
+        stream.extend_from_slice(&S_PUB32.to_le_bytes());
+        stream.extend_from_slice(&public.flags.to_le_bytes());
+        stream.extend_from_slice(&public.offset.to_le_bytes());
+        stream.extend_from_slice(&public.segment.to_le_bytes());
+        stream.extend_from_slice(public.name);
+        stream.resize(offsets.last().copied().unwrap() as usize + size, 0);
+    }
+
+    (stream, offsets)
+}
+
+/// Builds the name-based hash table used by the globals and publics streams.
+///
+fn gsi_hash(publics: &[PublicSymbol<'_>], offsets: &[u32]) -> Vec<u8> {
+    let mut buckets = vec![Vec::new(); GSI_HASH_BUCKETS];
+    for (public, offset) in publics.iter().zip(offsets) {
+        let bucket = hash_string_v1(public.name) as usize % GSI_HASH_BUCKETS;
+        buckets[bucket].push((public.name, *offset));
+    }
+
+    let mut records = Vec::new();
+    let mut bitmap = [0u32; GSI_BITMAP_WORDS];
+    let mut bucket_offsets = Vec::new();
+    let mut record_count = 0;
+    for (index, bucket) in buckets.iter_mut().enumerate() {
+        if bucket.is_empty() {
+            continue;
+        }
+        bitmap[index / 32] |= 1 << (index % 32);
+        bucket_offsets.push(record_count * GSI_HASH_RECORD_SIZE_IN_MEMORY);
+
+        bucket.sort_by(|(lhs, _), (rhs, _)| compare_names(lhs, rhs));
+        for (_, offset) in bucket.iter() {
+            // Offsets are stored biased by one; the reference count is always one
+            records.extend_from_slice(&(offset + 1).to_le_bytes());
+            records.extend_from_slice(&1u32.to_le_bytes());
+            record_count += 1;
+        }
+    }
+
+    let mut hash = Vec::new();
+    hash.extend_from_slice(&GSI_HASH_SIGNATURE.to_le_bytes());
+    hash.extend_from_slice(&GSI_HASH_VERSION.to_le_bytes());
+    hash.extend_from_slice(&(records.len() as u32).to_le_bytes());
+    hash.extend_from_slice(&((GSI_BITMAP_WORDS + bucket_offsets.len()) as u32 * 4).to_le_bytes());
+    hash.extend_from_slice(&records);
+    for word in &bitmap {
+        hash.extend_from_slice(&word.to_le_bytes());
+    }
+    for offset in bucket_offsets {
+        hash.extend_from_slice(&offset.to_le_bytes());
+    }
+
+    hash
+}
+
+/// Builds the address map of the publics stream: Record offsets sorted by address.
+///
+fn address_map(publics: &[PublicSymbol<'_>], offsets: &[u32]) -> Vec<u8> {
+    let mut order: Vec<_> = (0..publics.len()).collect();
+    order.sort_by(|&lhs, &rhs| {
+        let (lhs, rhs) = (&publics[lhs], &publics[rhs]);
+        (lhs.segment, lhs.offset)
+            .cmp(&(rhs.segment, rhs.offset))
+            .then_with(|| lhs.name.cmp(rhs.name))
+    });
+    order
+        .into_iter()
+        .flat_map(|index| offsets[index].to_le_bytes())
+        .collect()
+}
+
+/// The string hash function used by the globals and publics hash tables.
+///
+fn hash_string_v1(name: &[u8]) -> u32 {
+    let mut result = 0u32;
+    let mut chunks = name.chunks_exact(4);
+    for chunk in &mut chunks {
+        result ^= u32::from_le_bytes(chunk.try_into().unwrap());
+    }
+    let mut remainder = chunks.remainder();
+    if remainder.len() >= 2 {
+        result ^= u16::from_le_bytes([remainder[0], remainder[1]]) as u32;
+        remainder = &remainder[2..];
+    }
+    if let Some(&byte) = remainder.first() {
+        result ^= byte as u32;
+    }
+
+    result |= 0x2020_2020;
+    result ^= result >> 11;
+    result ^ (result >> 16)
+}
+
+/// Orders names within a hash bucket: Shorter names first, then case-insensitively for
+/// ASCII names, or bytewise otherwise.
+///
+fn compare_names(lhs: &[u8], rhs: &[u8]) -> std::cmp::Ordering {
+    lhs.len().cmp(&rhs.len()).then_with(|| {
+        if lhs.is_ascii() && rhs.is_ascii() {
+            lhs.to_ascii_lowercase().cmp(&rhs.to_ascii_lowercase())
+        } else {
+            lhs.cmp(rhs)
+        }
+    })
+}
+
+fn empty_type_stream() -> Vec<u8> {
+    let mut stream = Vec::with_capacity(TPI_HEADER_SIZE as usize);
+    stream.extend_from_slice(&TPI_VERSION_V80.to_le_bytes());
+    stream.extend_from_slice(&TPI_HEADER_SIZE.to_le_bytes());
+    stream.extend_from_slice(&TPI_FIRST_TYPE_INDEX.to_le_bytes()); // first type index
+    stream.extend_from_slice(&TPI_FIRST_TYPE_INDEX.to_le_bytes()); // one past the last
+    stream.extend_from_slice(&0u32.to_le_bytes()); // size of type records
+    stream.extend_from_slice(&INVALID_STREAM_INDEX.to_le_bytes()); // hash stream
+    stream.extend_from_slice(&INVALID_STREAM_INDEX.to_le_bytes()); // auxiliary hash stream
+    stream.extend_from_slice(&4u32.to_le_bytes()); // hash key size
+    stream.extend_from_slice(&0x3ffffu32.to_le_bytes()); // number of hash buckets
+    stream.resize(TPI_HEADER_SIZE as usize, 0); // hash value, index offset, and adjustment buffers
+    stream
+}
+
+fn stream(streams: &[Option<Vec<u8>>], index: u16) -> Option<&[u8]> {
+    streams
+        .get(index as usize)
+        .and_then(Option::as_ref)
+        .map(Vec::as_slice)
+}
+
+fn remove_stream(streams: &mut [Option<Vec<u8>>], index: u16) {
+    if let Some(stream) = streams.get_mut(index as usize) {
+        *stream = None;
+    }
+}
+
+/// Reads an MSF container, returning its block size and streams. Nil streams are
+/// represented by `None`.
+///
+fn read_msf(data: &[u8]) -> anyhow::Result<(usize, Vec<Option<Vec<u8>>>)> {
+    if data.len() < SUPER_BLOCK_SIZE || &data[..MSF_MAGIC.len()] != MSF_MAGIC {
+        bail!("Not a PDB file (or an unsupported PDB version)");
+    }
+    let block_size = read_u32(data, 32)? as usize;
+    if ![512, 1024, 2048, 4096].contains(&block_size) {
+        bail!("Invalid PDB block size {}", block_size);
+    }
+    let directory_size = read_u32(data, 44)? as usize;
+    let block_map = read_u32(data, 52)? as usize;
+
+    // The block map lists the blocks holding the stream directory
+    let directory_blocks = directory_size.div_ceil(block_size);
+    let block_map = slice(data, block_map * block_size, directory_blocks * 4)?;
+    let directory = read_blocks(data, block_size, block_map, directory_size)?;
+
+    let stream_count = read_u32(&directory, 0)? as usize;
+    let mut pos = 4 + stream_count * 4;
+    let mut streams = Vec::with_capacity(stream_count);
+    for index in 0..stream_count {
+        let size = read_u32(&directory, 4 + index * 4)?;
+        if size == NIL_STREAM_SIZE {
+            streams.push(None);
+            continue;
+        }
+        let size = size as usize;
+        let blocks = slice(&directory, pos, size.div_ceil(block_size) * 4)?;
+        pos += blocks.len();
+        streams.push(Some(read_blocks(data, block_size, blocks, size)?));
+    }
+
+    Ok((block_size, streams))
+}
+
+/// Concatenates the blocks listed in `blocks` (an array of 32-bit block indices), and
+/// truncates the result to `size` bytes.
+///
+fn read_blocks(
+    data: &[u8],
+    block_size: usize,
+    blocks: &[u8],
+    size: usize,
+) -> anyhow::Result<Vec<u8>> {
+    let mut contents = Vec::with_capacity(size);
+    for index in blocks.chunks_exact(4) {
+        let index = u32::from_le_bytes(index.try_into()?) as usize;
+        contents.extend_from_slice(slice(data, index * block_size, block_size)?);
+    }
+    contents.truncate(size);
+    Ok(contents)
+}
+
+/// Serializes `streams` into an MSF container.
+///
+fn write_msf(block_size: usize, streams: &[Option<Vec<u8>>]) -> anyhow::Result<Vec<u8>> {
+    // Block 0 holds the super block; blocks 1 and 2 of every interval of `block_size`
+    // blocks are reserved for the free block map
+    let mut file = vec![0u8; 3 * block_size];
+    let mut allocate = |data: &[u8]| -> Vec<u32> {
+        let mut blocks = Vec::new();
+        for chunk in data.chunks(block_size) {
+            let mut index = file.len() / block_size;
+            while index % block_size == 1 || index % block_size == 2 {
+                file.resize(file.len() + block_size, 0);
+                index += 1;
+            }
+            file.extend_from_slice(chunk);
+            file.resize((index + 1) * block_size, 0);
+            blocks.push(index as u32);
+        }
+        blocks
+    };
+
+    let mut directory = Vec::new();
+    let mut block_lists = Vec::new();
+    directory.extend_from_slice(&(streams.len() as u32).to_le_bytes());
+    for stream in streams {
+        match stream {
+            Some(data) => {
+                directory.extend_from_slice(&(data.len() as u32).to_le_bytes());
+                block_lists.extend(allocate(data));
+            }
+            None => directory.extend_from_slice(&NIL_STREAM_SIZE.to_le_bytes()),
+        }
+    }
+    for block in block_lists {
+        directory.extend_from_slice(&block.to_le_bytes());
+    }
+    let directory_blocks = allocate(&directory);
+    if directory_blocks.len() * 4 > block_size {
+        bail!("PDB stream directory too large");
+    }
+    let block_map: Vec<u8> = directory_blocks
+        .iter()
+        .flat_map(|block| block.to_le_bytes())
+        .collect();
+    let block_map = allocate(&block_map)[0];
+
+    // Make sure the free block map covers every block in the file
+    let bits_per_block = block_size * 8;
+    let fpm_blocks = (file.len() / block_size).div_ceil(bits_per_block);
+    let last_fpm_block = (fpm_blocks - 1) * block_size + 2;
+    if file.len() / block_size <= last_fpm_block {
+        file.resize((last_fpm_block + 1) * block_size, 0);
+    }
+    let block_count = file.len() / block_size;
+
+    // All blocks are in use; bits past the end of the file mark free blocks
+    let mut fpm = vec![0u8; fpm_blocks * block_size];
+    for bit in block_count..fpm.len() * 8 {
+        fpm[bit / 8] |= 1 << (bit % 8);
+    }
+    for (interval, chunk) in fpm.chunks(block_size).enumerate() {
+        for copy in 1..=2 {
+            let start = (interval * block_size + copy) * block_size;
+            file[start..start + block_size].copy_from_slice(chunk);
+        }
+    }
+
+    let mut super_block = Vec::with_capacity(SUPER_BLOCK_SIZE);
+    super_block.extend_from_slice(MSF_MAGIC);
+    super_block.extend_from_slice(&(block_size as u32).to_le_bytes());
+    super_block.extend_from_slice(&1u32.to_le_bytes()); // active free block map
+    super_block.extend_from_slice(&(block_count as u32).to_le_bytes());
+    super_block.extend_from_slice(&(directory.len() as u32).to_le_bytes());
+    super_block.extend_from_slice(&0u32.to_le_bytes());
+    super_block.extend_from_slice(&block_map.to_le_bytes());
+    file[..SUPER_BLOCK_SIZE].copy_from_slice(&super_block);
+
+    Ok(file)
+}
+
+fn slice(data: &[u8], pos: usize, len: usize) -> anyhow::Result<&[u8]> {
+    pos.checked_add(len)
+        .and_then(|end| data.get(pos..end))
+        .context("Unexpected end of PDB data")
+}
+
+fn read_u16(data: &[u8], pos: usize) -> anyhow::Result<u16> {
+    Ok(u16::from_le_bytes(slice(data, pos, 2)?.try_into()?))
+}
+
+fn read_u32(data: &[u8], pos: usize) -> anyhow::Result<u32> {
+    Ok(u32::from_le_bytes(slice(data, pos, 4)?.try_into()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn public(name: &'static [u8], offset: u32) -> PublicSymbol<'static> {
+        PublicSymbol {
+            flags: 2,
+            offset,
+            segment: 1,
+            name,
+        }
+    }
+
+    #[test]
+    fn hash_string_v1_matches_llvm() {
+        // Bucket indices agree with the publics hash table lld writes for these names
+        assert_eq!(hash_string_v1(b"mainCRTStartup"), 0x6962_66a9);
+        assert_eq!(
+            hash_string_v1(b"mainCRTStartup") as usize % GSI_HASH_BUCKETS,
+            1705
+        );
+        assert_eq!(hash_string_v1(b"exported_fn"), 0x2b30_27bb);
+        assert_eq!(
+            hash_string_v1(b"exported_fn") as usize % GSI_HASH_BUCKETS,
+            1979
+        );
+
+        // Trailing bytes are folded in as a 16-bit and an 8-bit value
+        assert_eq!(hash_string_v1(b""), 0x2024_0400);
+        assert_eq!(hash_string_v1(b"a"), 0x2024_0441);
+        assert_eq!(hash_string_v1(b"ab"), 0x2024_4649);
+        assert_eq!(hash_string_v1(b"abc"), 0x2024_460a);
+        assert_eq!(hash_string_v1(b"abcd"), 0x646f_8a62);
+    }
+
+    #[test]
+    fn gsi_hash_matches_llvm() {
+        let publics = [public(b"exported_fn", 16), public(b"mainCRTStartup", 64)];
+        let offsets = [0, 28];
+
+        // The hash table of the publics stream lld writes for the same symbols
+        let mut expected = Vec::new();
+        for word in [u32::MAX, 0xf12f_091a, 16, 524, 29, 1, 1, 1] {
+            expected.extend_from_slice(&word.to_le_bytes());
+        }
+        let mut bitmap = [0u32; GSI_BITMAP_WORDS];
+        bitmap[1705 / 32] |= 1 << (1705 % 32);
+        bitmap[1979 / 32] |= 1 << (1979 % 32);
+        for word in bitmap.iter().chain(&[0, 12]) {
+            expected.extend_from_slice(&word.to_le_bytes());
+        }
+
+        assert_eq!(gsi_hash(&publics, &offsets), expected);
+    }
+
+    #[test]
+    fn gsi_hash_sorts_buckets() {
+        // Words are combined with XOR, and their case is ignored, so all three names share
+        // a bucket. They are ordered shortest first, then case-insensitively.
+        let publics = [
+            public(b"efghABCD", 0),
+            public(b"abcdefgh", 0),
+            public(b"\x04\x04\x04\x0c", 0),
+        ];
+        let bucket = hash_string_v1(b"abcdefgh");
+        assert_eq!(hash_string_v1(b"efghABCD"), bucket);
+        assert_eq!(hash_string_v1(b"\x04\x04\x04\x0c"), bucket);
+
+        let hash = gsi_hash(&publics, &[0, 12, 24]);
+        let records: Vec<u32> = (0..6)
+            .map(|i| read_u32(&hash, 16 + i * 4).unwrap())
+            .collect();
+        assert_eq!(records, [25, 1, 13, 1, 1, 1]);
+    }
+
+    #[test]
+    fn empty_gsi_hash() {
+        let hash = gsi_hash(&[], &[]);
+        assert_eq!(hash.len(), 16 + GSI_BITMAP_WORDS * 4);
+        assert_eq!(read_u32(&hash, 8).unwrap(), 0);
+        assert_eq!(read_u32(&hash, 12).unwrap(), GSI_BITMAP_WORDS as u32 * 4);
+        assert!(hash[16..].iter().all(|&b| b == 0));
+    }
+
+    #[test]
+    fn msf_round_trip() {
+        let streams = vec![
+            Some(Vec::new()),
+            Some(vec![1; 100]),
+            None,
+            Some((0..3000).map(|i| i as u8).collect()),
+        ];
+        let file = write_msf(512, &streams).unwrap();
+        assert_eq!(&file[..MSF_MAGIC.len()], MSF_MAGIC);
+        assert_eq!(file.len() % 512, 0);
+        assert_eq!(read_u32(&file, 32).unwrap(), 512);
+        assert_eq!(read_u32(&file, 36).unwrap(), 1);
+        assert_eq!(read_u32(&file, 40).unwrap() as usize, file.len() / 512);
+
+        let (block_size, read) = read_msf(&file).unwrap();
+        assert_eq!(block_size, 512);
+        assert_eq!(read, streams);
+
+        // Writing what was read produces the same file
+        assert_eq!(write_msf(block_size, &read).unwrap(), file);
+    }
+
+    #[test]
+    fn msf_skips_free_block_maps() {
+        // Large enough to span several intervals of the free block map
+        let stream: Vec<u8> = (0..600 * 512).map(|i| (i / 512) as u8).collect();
+        let file = write_msf(512, &[Some(stream.clone())]).unwrap();
+        let block_count = file.len() / 512;
+        assert!(block_count > 512);
+
+        // Blocks 1 and 2 of each interval hold the free block map, marking the blocks
+        // past the end of the file as free
+        for interval in 0..block_count.div_ceil(512) {
+            let fpm = &file[(interval * 512 + 1) * 512..(interval * 512 + 2) * 512];
+            assert_eq!(
+                fpm,
+                &file[(interval * 512 + 2) * 512..(interval * 512 + 3) * 512]
+            );
+        }
+        assert_eq!(
+            file[512 + block_count / 8] >> (block_count % 8),
+            0xff >> (block_count % 8)
+        );
+        assert_eq!(file[512 + block_count / 8 - 1], 0);
+
+        let (_, read) = read_msf(&file).unwrap();
+        assert_eq!(read, [Some(stream)]);
+    }
+
+    #[test]
+    fn rejects_invalid_msf() {
+        assert!(read_msf(b"not a pdb").is_err());
+
+        let mut file = write_msf(512, &[Some(vec![1; 10])]).unwrap();
+        file[32..36].copy_from_slice(&1000u32.to_le_bytes());
+        assert!(read_msf(&file).is_err());
+
+        let file = write_msf(512, &[Some(vec![1; 10])]).unwrap();
+        assert!(read_msf(&file[..3 * 512]).is_err());
+    }
+
+    #[test]
+    fn rejects_oversized_module_information() {
+        let mut dbi = vec![0; DBI_HEADER_SIZE];
+        dbi[..4].copy_from_slice(&u32::MAX.to_le_bytes());
+        dbi[24..28].copy_from_slice(&(MODULE_INFO_HEADER_SIZE as u32).to_le_bytes());
+        let streams = [None, None, None, Some(dbi)];
+        let file = write_msf(512, &streams).unwrap();
+
+        let error = strip_private_symbols(&file).unwrap_err();
+        assert_eq!(error.to_string(), "DBI module information exceeds stream");
+    }
+}
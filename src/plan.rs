@@ -0,0 +1,517 @@
+//! Implements staging of the files written when generating a package.
+//!
+//! Rather than writing files one by one, generation first collects all writes into a
+//! [`Plan`]. The plan can be checked for conflicts with existing files, and is then applied
+//! as a whole: If any write fails, all previous writes are undone, restoring overwritten
+//! files and removing files and directories that didn't exist before.
+//!
+//! Directories can be removed as part of a plan as well. They are moved aside while the
+//! plan is applied, and only deleted once all writes succeeded.
+//!
+//! Alternatively, a plan can be printed without applying it (`--dry-run`), either for
+//! humans or as JSON for tools.
+//!
+use std::{
+    collections::BTreeMap,
+    ffi::OsStr,
+    fmt::Write,
+    fs,
+    path::{Path, PathBuf},
+    process::Command,
+    str::FromStr,
+};
+
+use anyhow::{anyhow, bail, Context};
+use toml_edit as toml;
+
+use crate::ops::status;
+
+/// A single file write of a [`Plan`].
+///
+struct PlannedFile {
+    path: PathBuf,
+    /// The new contents, or `None` if the file or directory gets removed.
+    contents: Option<Vec<u8>>,
+    /// Whether the file is expected to exist already, and gets updated rather than
+    /// created (e.g. *Cargo.toml*).
+    update: bool,
+}
+
+impl PlannedFile {
+    fn action(&self) -> Action {
+        if self.contents.is_none() {
+            Action::Remove
+        } else if self.update {
+            Action::Update
+        } else if !self.path.exists() {
+            Action::Create
+        } else if fs::read(&self.path).is_ok_and(|existing| Some(existing) == self.contents) {
+            Action::Unchanged
+        } else {
+            Action::Overwrite
+        }
+    }
+}
+
+/// What applying a [`PlannedFile`] does to the file system.
+///
+#[derive(Clone, Copy)]
+enum Action {
+    Create,
+    Overwrite,
+    Update,
+    Unchanged,
+    Remove,
+}
+
+impl Action {
+    fn name(self) -> &'static str {
+        match self {
+            Action::Create => "create",
+            Action::Overwrite => "overwrite",
+            Action::Update => "update",
+            Action::Unchanged => "unchanged",
+            Action::Remove => "remove",
+        }
+    }
+}
+
+/// A key of *Cargo.toml* that gets added, changed, or removed, with values in TOML
+/// notation.
+///
+struct KeyChange {
+    key: String,
+    previous: Option<String>,
+    value: Option<String>,
+}
+
+impl KeyChange {
+    fn action(&self) -> &'static str {
+        match (&self.previous, &self.value) {
+            (None, _) => "add",
+            (Some(_), Some(_)) => "change",
+            (Some(_), None) => "remove",
+        }
+    }
+}
+
+#[derive(Default)]
+pub(crate) struct Plan {
+    /// The command line of the command run prior to writing files, if any.
+    command: Option<Vec<String>>,
+    files: Vec<PlannedFile>,
+    manifest_changes: Vec<KeyChange>,
+}
+
+impl Plan {
+    pub(crate) fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds writing binary/string content into a file. The destination directory is
+    /// constructed starting from `base_dir` and subsequently appending all `sub_dir` parts,
+    /// if any.
+    ///
+    pub(crate) fn write<'a>(
+        &mut self,
+        base_dir: &Path,
+        sub_dir: Option<&'a [&'a str]>,
+        dest_name: impl AsRef<OsStr>,
+        contents: impl AsRef<[u8]>,
+    ) {
+        let mut path: PathBuf = base_dir.into();
+        for sub_dir in sub_dir.unwrap_or_default() {
+            path.push(sub_dir);
+        }
+        path.push(dest_name.as_ref());
+
+        self.add(path, Some(contents.as_ref().to_owned()), false);
+    }
+
+    /// Adds updating the existing file at `path` with `contents`.
+    ///
+    pub(crate) fn update(&mut self, path: PathBuf, contents: impl Into<Vec<u8>>) {
+        self.add(path, Some(contents.into()), true);
+    }
+
+    /// Adds removing the file or directory at `path`, including its contents.
+    ///
+    pub(crate) fn remove(&mut self, path: PathBuf) {
+        self.add(path, None, false);
+    }
+
+    /// Adds updating the *Cargo.toml* file at `path` from `previous` to `contents`,
+    /// recording the keys that get added or changed.
+    ///
+    pub(crate) fn update_manifest(
+        &mut self,
+        path: PathBuf,
+        previous: &str,
+        contents: String,
+    ) -> anyhow::Result<()> {
+        let mut previous_values = BTreeMap::new();
+        flatten_table(
+            previous.parse::<toml::Document>()?.as_table(),
+            "",
+            &mut previous_values,
+        );
+        let mut values = BTreeMap::new();
+        flatten_table(
+            contents.parse::<toml::Document>()?.as_table(),
+            "",
+            &mut values,
+        );
+
+        self.manifest_changes = values
+            .into_iter()
+            .filter_map(|(key, value)| match previous_values.remove(&key) {
+                Some(previous) if previous == value => None,
+                previous => Some(KeyChange {
+                    key,
+                    previous,
+                    value: Some(value),
+                }),
+            })
+            .collect();
+        // Whatever is left didn't make it into `contents`
+        self.manifest_changes
+            .extend(
+                previous_values
+                    .into_iter()
+                    .map(|(key, previous)| KeyChange {
+                        key,
+                        previous: Some(previous),
+                        value: None,
+                    }),
+            );
+        self.update(path, contents);
+
+        Ok(())
+    }
+
+    /// Records `command` as being run prior to writing files.
+    ///
+    pub(crate) fn set_command(&mut self, command: &Command) {
+        self.command = Some(
+            std::iter::once(command.get_program())
+                .chain(command.get_args())
+                .map(|arg| arg.to_string_lossy().into_owned())
+                .collect(),
+        );
+    }
+
+    /// Fails if applying the plan would overwrite existing files with different contents,
+    /// other than files that are meant to be updated. `force` skips this check.
+    ///
+    pub(crate) fn check_conflicts(&self, force: bool) -> anyhow::Result<()> {
+        if force {
+            return Ok(());
+        }
+
+        let conflicts = self
+            .files
+            .iter()
+            .filter(|file| matches!(file.action(), Action::Overwrite))
+            .collect::<Vec<_>>();
+        if !conflicts.is_empty() {
+            let mut message = String::from("The following files exist already:");
+            for file in conflicts {
+                write!(message, "\n  {}", file.path.display())?;
+            }
+            message.push_str("\nUse --force to overwrite them");
+            bail!(message);
+        }
+
+        Ok(())
+    }
+
+    /// Writes all files. On failure, all changes made up to that point are rolled back.
+    ///
+    pub(crate) fn apply(&self) -> anyhow::Result<()> {
+        let mut journal = Journal::default();
+        for file in &self.files {
+            let result = match &file.contents {
+                Some(contents) => journal.write(&file.path, contents),
+                None => journal.remove(&file.path),
+            };
+            if let Err(e) = result {
+                journal.roll_back();
+                return Err(e);
+            }
+        }
+        journal.finish();
+
+        Ok(())
+    }
+
+    /// Prints the plan without applying it.
+    ///
+    pub(crate) fn print(&self, format: MessageFormat) {
+        match format {
+            MessageFormat::Human => self.print_human(),
+            MessageFormat::Json => println!("{}", self.to_json()),
+        }
+    }
+
+    fn print_human(&self) {
+        if let Some(command) = &self.command {
+            status("Would run", &command.join(" "));
+        }
+        for file in &self.files {
+            let action = file.action();
+            let mut action_name = action.name().to_owned();
+            action_name[..1].make_ascii_uppercase();
+            match &file.contents {
+                Some(contents) => status(
+                    &action_name,
+                    &format!("{} ({} bytes)", file.path.display(), contents.len()),
+                ),
+                None => status(&action_name, &file.path.display()),
+            }
+            if let Action::Update = action {
+                if file.path.file_name() == Some(OsStr::new("Cargo.toml")) {
+                    for change in &self.manifest_changes {
+                        match (&change.previous, &change.value) {
+                            (None, Some(value)) => {
+                                status("Add", &format!("{} = {}", change.key, value))
+                            }
+                            (Some(previous), Some(value)) => status(
+                                "Change",
+                                &format!("{} = {} (was {})", change.key, value, previous),
+                            ),
+                            (Some(previous), None) => {
+                                status("Remove", &format!("{} (was {})", change.key, previous))
+                            }
+                            (None, None) => {}
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    fn to_json(&self) -> String {
+        let command = match &self.command {
+            Some(command) => format!(
+                "[{}]",
+                command
+                    .iter()
+                    .map(|arg| json_string(arg))
+                    .collect::<Vec<_>>()
+                    .join(",")
+            ),
+            None => "null".to_owned(),
+        };
+        let files = self
+            .files
+            .iter()
+            .map(|file| {
+                format!(
+                    r#"{{"path":{},"action":"{}","size":{}}}"#,
+                    json_string(&file.path.to_string_lossy()),
+                    file.action().name(),
+                    file.contents
+                        .as_ref()
+                        .map_or_else(|| "null".to_owned(), |contents| contents.len().to_string())
+                )
+            })
+            .collect::<Vec<_>>()
+            .join(",");
+        let manifest_changes = self
+            .manifest_changes
+            .iter()
+            .map(|change| {
+                format!(
+                    r#"{{"key":{},"action":"{}","previous":{},"value":{}}}"#,
+                    json_string(&change.key),
+                    change.action(),
+                    change
+                        .previous
+                        .as_deref()
+                        .map_or_else(|| "null".to_owned(), json_string),
+                    change
+                        .value
+                        .as_deref()
+                        .map_or_else(|| "null".to_owned(), json_string)
+                )
+            })
+            .collect::<Vec<_>>()
+            .join(",");
+
+        format!(
+            r#"{{"command":{},"files":[{}],"manifest_changes":[{}]}}"#,
+            command, files, manifest_changes
+        )
+    }
+
+    fn add(&mut self, path: PathBuf, contents: Option<Vec<u8>>, mut update: bool) {
+        // Later writes to the same file supersede earlier ones, but a file that gets
+        // updated remains one (e.g. *src/main.rs* generated by `cargo new`)
+        if let Some(index) = self.files.iter().position(|file| file.path == path) {
+            let superseded = self.files.remove(index);
+            update |= superseded.update;
+        }
+        self.files.push(PlannedFile {
+            path,
+            contents,
+            update,
+        });
+    }
+}
+
+/// Records the changes made while applying a [`Plan`], so that they can be undone.
+///
+#[derive(Default)]
+struct Journal {
+    changes: Vec<Change>,
+}
+
+enum Change {
+    CreatedDir(PathBuf),
+    /// A written file, along with its previous contents, if any.
+    WroteFile(PathBuf, Option<Vec<u8>>),
+    /// A removed file or directory, moved aside to `backup`.
+    Removed {
+        path: PathBuf,
+        backup: PathBuf,
+    },
+}
+
+impl Journal {
+    fn write(&mut self, path: &Path, contents: &[u8]) -> anyhow::Result<()> {
+        if let Some(dir) = path.parent() {
+            // Create missing directories outermost first, so that they can be removed in
+            // reverse order
+            let mut missing = dir
+                .ancestors()
+                .take_while(|dir| !dir.as_os_str().is_empty() && !dir.exists())
+                .map(Path::to_path_buf)
+                .collect::<Vec<_>>();
+            missing.reverse();
+            for dir in missing {
+                fs::create_dir(&dir)
+                    .with_context(|| format!("Creating directory {}", dir.display()))?;
+                self.changes.push(Change::CreatedDir(dir));
+            }
+        }
+
+        let previous = if path.exists() {
+            let contents = fs::read(path).with_context(|| format!("Reading {}", path.display()))?;
+            Some(contents)
+        } else {
+            None
+        };
+        self.changes
+            .push(Change::WroteFile(path.to_path_buf(), previous));
+        fs::write(path, contents).with_context(|| format!("Writing {}", path.display()))?;
+
+        Ok(())
+    }
+
+    fn remove(&mut self, path: &Path) -> anyhow::Result<()> {
+        let mut backup = path.as_os_str().to_owned();
+        backup.push(".cargo-uwp-removed");
+        let backup = PathBuf::from(backup);
+        fs::rename(path, &backup).with_context(|| format!("Removing {}", path.display()))?;
+        self.changes.push(Change::Removed {
+            path: path.to_path_buf(),
+            backup,
+        });
+
+        Ok(())
+    }
+
+    /// Deletes removed files and directories for good.
+    ///
+    fn finish(self) {
+        for change in self.changes {
+            if let Change::Removed { backup, .. } = change {
+                let _ = if backup.is_dir() {
+                    fs::remove_dir_all(&backup)
+                } else {
+                    fs::remove_file(&backup)
+                };
+            }
+        }
+    }
+
+    /// Undoes all recorded changes, on a best effort basis.
+    ///
+    fn roll_back(self) {
+        for change in self.changes.into_iter().rev() {
+            let _ = match change {
+                Change::CreatedDir(dir) => fs::remove_dir(&dir),
+                Change::WroteFile(path, Some(contents)) => fs::write(&path, contents),
+                Change::WroteFile(path, None) => fs::remove_file(&path),
+                Change::Removed { path, backup } => fs::rename(&backup, &path),
+            };
+        }
+    }
+}
+
+/// Output format of a [`Plan`] that isn't applied.
+///
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum MessageFormat {
+    Human,
+    Json,
+}
+
+impl FromStr for MessageFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "human" => Ok(MessageFormat::Human),
+            "json" => Ok(MessageFormat::Json),
+            _ => Err(anyhow!(
+                "Unknown message format '{}' (expected human or json)",
+                s
+            )),
+        }
+    }
+}
+
+/// Collects all values nested in `table` as `(dotted key, value in TOML notation)` pairs.
+///
+fn flatten_table(table: &toml::Table, prefix: &str, values: &mut BTreeMap<String, String>) {
+    for (key, item) in table.iter() {
+        let key = format!("{}{}", prefix, key);
+        if let Some(table) = item.as_table() {
+            flatten_table(table, &format!("{}.", key), values);
+        } else if let Some(tables) = item.as_array_of_tables() {
+            for (index, table) in tables.iter().enumerate() {
+                flatten_table(table, &format!("{}[{}].", key, index), values);
+            }
+        } else if let Some(table) = item.as_inline_table() {
+            for (inline_key, value) in table.iter() {
+                values.insert(
+                    format!("{}.{}", key, inline_key),
+                    value.to_string().trim().to_owned(),
+                );
+            }
+        } else if let Some(value) = item.as_value() {
+            values.insert(key, value.to_string().trim().to_owned());
+        }
+    }
+}
+
+fn json_string(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len() + 2);
+    escaped.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(escaped, "\\u{:04x}", c as u32);
+            }
+            c => escaped.push(c),
+        }
+    }
+    escaped.push('"');
+    escaped
+}
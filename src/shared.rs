@@ -10,6 +10,9 @@ pub const PACKAGE_IDENTITY_VERSION_DEFAULT: &str = "0.0.0.0";
 pub const PACKAGE_PHONE_ID_KEY: &str = "package-phoneidentity-productid";
 pub const PACKAGE_PHONE_ID_DEFAULT: &str = "00000000-0000-0000-0000-000000000000";
 
+pub const PACKAGE_PHONE_PUBLISHER_ID_KEY: &str = "package-phoneidentity-publisherid";
+pub const PACKAGE_PHONE_PUBLISHER_ID_DEFAULT: &str = "00000000-0000-0000-0000-000000000000";
+
 pub const PACKAGE_DISPLAY_NAME_KEY: &str = "package-properties-displayname";
 pub const PACKAGE_DISPLAY_NAME_DEFAULT: &str = "AppNameDefault";
 
@@ -21,3 +24,33 @@ pub const PACKAGE_VISUAL_DISPLAY_NAME_DEFAULT: &str = "My Rust UWP application";
 
 pub const PACKAGE_VISUAL_DESCRIPTION_KEY: &str = "package-applications-visualelements-description";
 pub const PACKAGE_VISUAL_DESCRIPTION_DEFAULT: &str = "A UWP application written in Rust";
+
+/// Environment variable overriding the directory the generated files are written to, and
+/// `cargo uwp` looks for build artifacts in. This needs to be the directory the executable
+/// is compiled to; relative paths are relative to the package root.
+pub const ARTIFACT_DIR_ENV: &str = "CARGO_UWP_ARTIFACT_DIR";
+
+/// Parses a single line of a *FileMapping.ini* file. Returns the pair of `(local path,
+/// package-relative path)` if the line describes a mapping, or `None` otherwise (e.g. for
+/// section headers).
+///
+pub fn parse_mapping(line: &str) -> Option<(String, String)> {
+    // The shortest valid line is `"a" "b"`, i.e. 7 characters long
+    if line.len() < 7 || !line.starts_with('\"') || !line.ends_with('\"') {
+        return None;
+    }
+    // Strip first and last quotation mark (")
+    let line = &line[1..line.len() - 1];
+
+    let end = line.find('\"');
+    let begin = line.rfind('\"');
+    if let (Some(end), Some(begin)) = (end, begin) {
+        if end != begin {
+            Some((line[..end].to_owned(), line[begin + 1..].to_owned()))
+        } else {
+            None
+        }
+    } else {
+        None
+    }
+}
@@ -0,0 +1,526 @@
+//! Implements signing of packages and bundles (*AppxSignature.p7x*).
+//!
+//! The signature is an Authenticode PKCS #7 `SignedData` structure, prefixed with the
+//! magic `PKCX`, and stored as the last entry of the archive. Rather than a digest of the
+//! file as a whole, the signed content holds digests of the individual parts of the
+//! archive:
+//!
+//! * `AXPC`: The local file records of all entries, excluding the signature.
+//! * `AXCD`: The central directory, excluding the signature.
+//! * `AXCT`: The uncompressed *\[Content_Types\].xml*.
+//! * `AXBM`: The uncompressed *AppxBlockMap.xml*, which in turn covers all payload files.
+//! * `AXCI`: The uncompressed *AppxMetadata\CodeIntegrity.cat*, if present.
+//!
+use std::{fs, path::Path};
+
+use anyhow::{anyhow, bail, Context};
+use p12_keystore::KeyStore;
+use rsa::{
+    pkcs8::{DecodePrivateKey, EncodePublicKey},
+    Pkcs1v15Sign, RsaPrivateKey, RsaPublicKey,
+};
+use sha2::{Sha256, Sha384, Sha512};
+use x509_cert::{
+    der::{Decode, Encode, Tag, Tagged},
+    Certificate,
+};
+
+use crate::appx::{
+    self, HashAlgorithm, APPX_BLOCK_MAP_NAME, APPX_SIGNATURE_NAME, CONTENT_TYPES_NAME,
+};
+use crate::asn1::{
+    self, algorithm_identifier, der, integer, oid, sequence, TAG_CONTEXT_0, TAG_OCTET_STRING,
+    TAG_SEQUENCE, TAG_SET,
+};
+use crate::bundle::BUNDLE_MANIFEST_NAME;
+use crate::zip::{ZipArchive, ZipWriter};
+
+const CODE_INTEGRITY_NAME: &str = "AppxMetadata\\CodeIntegrity.cat";
+const SIGNATURE_CONTENT_TYPE: &str = "application/vnd.ms-appx.signature";
+
+/// Subject interface package GUIDs, identifying the kind of archive that is signed.
+///
+const APPX_SIP_GUID: [u8; 16] = [
+    0x4b, 0xdf, 0xc5, 0x0a, 0x07, 0xce, 0xe2, 0x4d, 0xb7, 0x6e, 0x23, 0xc8, 0x39, 0xa0, 0x9f, 0xd1,
+];
+const APPX_BUNDLE_SIP_GUID: [u8; 16] = [
+    0xb3, 0x58, 0x5f, 0x0f, 0xde, 0xaa, 0x9a, 0x4b, 0xa4, 0x34, 0x95, 0x74, 0x2d, 0x92, 0xec, 0xeb,
+];
+
+const OID_SIGNED_DATA: &str = "1.2.840.113549.1.7.2";
+const OID_CONTENT_TYPE: &str = "1.2.840.113549.1.9.3";
+const OID_MESSAGE_DIGEST: &str = "1.2.840.113549.1.9.4";
+const OID_RSA_ENCRYPTION: &str = "1.2.840.113549.1.1.1";
+const OID_SPC_INDIRECT_DATA: &str = "1.3.6.1.4.1.311.2.1.4";
+const OID_SPC_STATEMENT_TYPE: &str = "1.3.6.1.4.1.311.2.1.11";
+const OID_SPC_SP_OPUS_INFO: &str = "1.3.6.1.4.1.311.2.1.12";
+const OID_SPC_INDIVIDUAL_SP_KEY_PURPOSE: &str = "1.3.6.1.4.1.311.2.1.21";
+const OID_SPC_SIPINFO: &str = "1.3.6.1.4.1.311.2.1.30";
+
+/// A code signing certificate, together with its private key.
+///
+pub(crate) struct SigningCertificate {
+    key: RsaPrivateKey,
+    certificate: Certificate,
+    /// The DER encoded certificate chain, starting with the signing certificate.
+    chain: Vec<Vec<u8>>,
+}
+
+impl SigningCertificate {
+    /// Reads the certificate and private key from the PKCS #12 file at `path`.
+    ///
+    pub(crate) fn from_pfx(path: &Path, password: &str) -> anyhow::Result<Self> {
+        let data = fs::read(path).with_context(|| format!("Reading {}", path.display()))?;
+        let key_store = KeyStore::from_pkcs12(&data, password)
+            .map_err(|e| anyhow!("Reading {}: {}", path.display(), e))?;
+        let (_, key_chain) = key_store
+            .private_key_chain()
+            .ok_or_else(|| anyhow!("{} doesn't contain a private key", path.display()))?;
+
+        let key = RsaPrivateKey::from_pkcs8_der(key_chain.key())
+            .map_err(|_| anyhow!("The private key in {} is not an RSA key", path.display()))?;
+        let chain: Vec<_> = key_chain
+            .chain()
+            .iter()
+            .map(|certificate| certificate.as_der().to_owned())
+            .collect();
+        let certificate = chain
+            .first()
+            .ok_or_else(|| anyhow!("{} doesn't contain a certificate", path.display()))?;
+        let certificate = Certificate::from_der(certificate)
+            .with_context(|| format!("Parsing the certificate in {}", path.display()))?;
+
+        let public_key = RsaPublicKey::from(&key).to_public_key_der()?;
+        if certificate
+            .tbs_certificate
+            .subject_public_key_info
+            .to_der()?
+            != public_key.as_bytes()
+        {
+            bail!(
+                "The certificate in {} doesn't belong to its private key",
+                path.display()
+            );
+        }
+
+        Ok(Self {
+            key,
+            certificate,
+            chain,
+        })
+    }
+
+    /// Returns the certificate subject, formatted the way publishers are spelled out in
+    /// package manifests (e.g. `CN=Contoso, O=Contoso Corporation, C=US`).
+    ///
+    pub(crate) fn subject(&self) -> String {
+        asn1::format_distinguished_name(&subject_attributes(&self.certificate))
+    }
+
+    /// Returns whether the certificate subject equals the package identity `publisher`.
+    ///
+    pub(crate) fn matches_publisher(&self, publisher: &str) -> bool {
+        match asn1::parse_distinguished_name(publisher) {
+            Some(publisher) => publisher == subject_attributes(&self.certificate),
+            None => false,
+        }
+    }
+
+    /// Returns the DER encoded Authenticode `SignedData` structure over `digests`, the
+    /// concatenated digests of the archive parts.
+    ///
+    fn sign(
+        &self,
+        digests: &[u8],
+        sip_guid: &[u8],
+        hash_algorithm: HashAlgorithm,
+    ) -> anyhow::Result<Vec<u8>> {
+        let digest_algorithm = algorithm_identifier(digest_algorithm_oid(hash_algorithm))?;
+
+        let sip_info = sequence(&[
+            integer(0x0101_0000),
+            der(TAG_OCTET_STRING, sip_guid),
+            integer(0),
+            integer(0),
+            integer(0),
+            integer(0),
+            integer(0),
+        ]);
+        let indirect_data = [
+            sequence(&[oid(OID_SPC_SIPINFO)?, sip_info]),
+            sequence(&[digest_algorithm.clone(), der(TAG_OCTET_STRING, digests)]),
+        ]
+        .concat();
+
+        // Authenticode hashes the contents of `SpcIndirectDataContent`, excluding its tag
+        // and length
+        let content_digest = hash_algorithm.digest(&indirect_data);
+        let mut attributes = [
+            attribute(OID_CONTENT_TYPE, oid(OID_SPC_INDIRECT_DATA)?)?,
+            attribute(OID_SPC_SP_OPUS_INFO, sequence(&[]))?,
+            attribute(
+                OID_SPC_STATEMENT_TYPE,
+                sequence(&[oid(OID_SPC_INDIVIDUAL_SP_KEY_PURPOSE)?]),
+            )?,
+            attribute(OID_MESSAGE_DIGEST, der(TAG_OCTET_STRING, &content_digest))?,
+        ];
+        attributes.sort();
+        let attributes = attributes.concat();
+
+        // The signature covers the authenticated attributes, encoded as a SET OF
+        let attributes_digest = hash_algorithm.digest(&der(TAG_SET, &attributes));
+        let padding = match hash_algorithm {
+            HashAlgorithm::Sha256 => Pkcs1v15Sign::new::<Sha256>(),
+            HashAlgorithm::Sha384 => Pkcs1v15Sign::new::<Sha384>(),
+            HashAlgorithm::Sha512 => Pkcs1v15Sign::new::<Sha512>(),
+        };
+        let signature = self.key.sign(padding, &attributes_digest)?;
+
+        let tbs_certificate = &self.certificate.tbs_certificate;
+        let signer_info = sequence(&[
+            integer(1),
+            sequence(&[
+                tbs_certificate.issuer.to_der()?,
+                tbs_certificate.serial_number.to_der()?,
+            ]),
+            digest_algorithm.clone(),
+            der(TAG_CONTEXT_0, &attributes),
+            algorithm_identifier(OID_RSA_ENCRYPTION)?,
+            der(TAG_OCTET_STRING, &signature),
+        ]);
+        let signed_data = sequence(&[
+            integer(1),
+            der(TAG_SET, &digest_algorithm),
+            sequence(&[
+                oid(OID_SPC_INDIRECT_DATA)?,
+                der(TAG_CONTEXT_0, &der(TAG_SEQUENCE, &indirect_data)),
+            ]),
+            der(TAG_CONTEXT_0, &self.chain.concat()),
+            der(TAG_SET, &signer_info),
+        ]);
+
+        Ok(sequence(&[
+            oid(OID_SIGNED_DATA)?,
+            der(TAG_CONTEXT_0, &signed_data),
+        ]))
+    }
+}
+
+/// Signs the package or bundle at `path` in place, replacing any existing signature.
+///
+/// The package must be signed using the hash algorithm its block map was created with.
+/// When `hash_algorithm` is `None`, that algorithm is picked automatically.
+///
+pub(crate) fn sign_package(
+    path: &Path,
+    certificate: &SigningCertificate,
+    hash_algorithm: Option<HashAlgorithm>,
+) -> anyhow::Result<()> {
+    let archive = ZipArchive::open(path).with_context(|| format!("Reading {}", path.display()))?;
+    // Footprint files are stored under their plain names
+    let read = |name: &str| -> anyhow::Result<Vec<u8>> {
+        let entry = archive
+            .find(name)
+            .ok_or_else(|| anyhow!("{} doesn't contain {}", path.display(), name))?;
+        Ok(archive.read(entry)?)
+    };
+
+    let block_map = read(APPX_BLOCK_MAP_NAME)?;
+    let block_map_algorithm = block_map_hash_algorithm(&block_map)
+        .with_context(|| format!("Reading {} from {}", APPX_BLOCK_MAP_NAME, path.display()))?;
+    let hash_algorithm = hash_algorithm.unwrap_or(block_map_algorithm);
+    if hash_algorithm != block_map_algorithm {
+        bail!(
+            "The block map of {} was created using {}, and cannot be signed using {}",
+            path.display(),
+            block_map_algorithm,
+            hash_algorithm
+        );
+    }
+
+    // Register the content type of the signature, unless a previous signature already did
+    let content_types = String::from_utf8(read(CONTENT_TYPES_NAME)?)
+        .with_context(|| format!("Reading {} from {}", CONTENT_TYPES_NAME, path.display()))?;
+    let signature_part = format!("/{}", appx::part_name(APPX_SIGNATURE_NAME));
+    let content_types = if content_types
+        .to_lowercase()
+        .contains(&signature_part.to_lowercase())
+    {
+        content_types
+    } else {
+        let end = content_types
+            .rfind("</Types>")
+            .ok_or_else(|| anyhow!("{} in {} is malformed", CONTENT_TYPES_NAME, path.display()))?;
+        format!(
+            "{}<Override PartName=\"{}\" ContentType=\"{}\"/>{}",
+            &content_types[..end],
+            signature_part,
+            SIGNATURE_CONTENT_TYPE,
+            &content_types[end..]
+        )
+    };
+
+    // Rewrite the archive with the (updated) content types and the signature as the last
+    // entries. All other entries are copied verbatim, keeping the block map valid.
+    let mut zip = ZipWriter::new(Vec::new());
+    for entry in archive.entries() {
+        if entry.name.eq_ignore_ascii_case(CONTENT_TYPES_NAME)
+            || entry.name.eq_ignore_ascii_case(APPX_SIGNATURE_NAME)
+        {
+            continue;
+        }
+        zip.copy_entry(entry, &archive.raw_record(entry)?)?;
+    }
+    zip.write_file(CONTENT_TYPES_NAME, content_types.as_bytes(), true)?;
+
+    let mut digests = b"APPX".to_vec();
+    let mut add_digest = |tag: &[u8], data: &[u8]| {
+        digests.extend_from_slice(tag);
+        digests.extend_from_slice(&hash_algorithm.digest(data));
+    };
+    add_digest(b"AXPC", zip.get_ref());
+    add_digest(b"AXCD", &zip.trailer());
+    add_digest(b"AXCT", content_types.as_bytes());
+    add_digest(b"AXBM", &block_map);
+    if let Some(entry) = archive.find(&appx::part_name(CODE_INTEGRITY_NAME)) {
+        add_digest(b"AXCI", &archive.read(entry)?);
+    }
+
+    let sip_guid = if archive
+        .find(&appx::part_name(BUNDLE_MANIFEST_NAME))
+        .is_some()
+    {
+        &APPX_BUNDLE_SIP_GUID
+    } else {
+        &APPX_SIP_GUID
+    };
+    let mut signature = b"PKCX".to_vec();
+    signature.extend_from_slice(&certificate.sign(&digests, sip_guid, hash_algorithm)?);
+    zip.write_file(APPX_SIGNATURE_NAME, &signature, true)?;
+    let data = zip.finish()?;
+
+    // Replace the package only once the signed archive is complete
+    let temp_path = path.with_extension("signing");
+    fs::write(&temp_path, data)
+        .and_then(|_| fs::rename(&temp_path, path))
+        .map_err(|e| {
+            let _ = fs::remove_file(&temp_path);
+            anyhow!("Writing {}: {}", path.display(), e)
+        })?;
+
+    Ok(())
+}
+
+fn block_map_hash_algorithm(block_map: &[u8]) -> anyhow::Result<HashAlgorithm> {
+    let block_map = std::str::from_utf8(block_map)?;
+    let document = roxmltree::Document::parse(block_map.trim_start_matches('\u{feff}'))?;
+    let hash_method = document
+        .root_element()
+        .attribute("HashMethod")
+        .ok_or_else(|| anyhow!("Missing HashMethod attribute"))?;
+    HashAlgorithm::from_block_map_uri(hash_method)
+        .ok_or_else(|| anyhow!("Unsupported hash method {}", hash_method))
+}
+
+/// Returns the attributes of the certificate subject as `(short name, value)` pairs, in
+/// the order package manifests spell them out (i.e. most specific first).
+///
+fn subject_attributes(certificate: &Certificate) -> Vec<(String, String)> {
+    let mut attributes = Vec::new();
+    for rdn in certificate.tbs_certificate.subject.0.iter().rev() {
+        for attribute in rdn.0.iter() {
+            let name = asn1::attribute_short_name(&attribute.oid.to_string());
+            let value = attribute.value.value();
+            let value = if attribute.value.tag() == Tag::BmpString {
+                let units: Vec<_> = value
+                    .chunks_exact(2)
+                    .map(|unit| u16::from_be_bytes([unit[0], unit[1]]))
+                    .collect();
+                String::from_utf16_lossy(&units)
+            } else {
+                String::from_utf8_lossy(value).into_owned()
+            };
+            attributes.push((name, value));
+        }
+    }
+    attributes
+}
+
+fn digest_algorithm_oid(hash_algorithm: HashAlgorithm) -> &'static str {
+    match hash_algorithm {
+        HashAlgorithm::Sha256 => "2.16.840.1.101.3.4.2.1",
+        HashAlgorithm::Sha384 => "2.16.840.1.101.3.4.2.2",
+        HashAlgorithm::Sha512 => "2.16.840.1.101.3.4.2.3",
+    }
+}
+
+fn attribute(attribute_type: &str, value: Vec<u8>) -> anyhow::Result<Vec<u8>> {
+    Ok(sequence(&[oid(attribute_type)?, der(TAG_SET, &value)]))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{convert::TryInto, path::PathBuf, time::Duration};
+
+    use super::*;
+    use crate::cert::SelfSignedCertificate;
+
+    const PUBLISHER: &str = "CN=Contoso, O=\"Contoso, Inc.\", C=US";
+
+    /// Returns an empty directory private to the calling test.
+    ///
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("cargo-uwp-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn certificate(dir: &Path) -> SigningCertificate {
+        let certificate =
+            SelfSignedCertificate::new(PUBLISHER, Duration::from_secs(24 * 60 * 60)).unwrap();
+        let path = dir.join("test.pfx");
+        fs::write(&path, certificate.to_pfx("test", "password").unwrap()).unwrap();
+        SigningCertificate::from_pfx(&path, "password").unwrap()
+    }
+
+    fn write_package(path: &Path, hash_algorithm: HashAlgorithm) {
+        let block_map = format!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?><BlockMap \
+             xmlns=\"http://schemas.microsoft.com/appx/2010/blockmap\" HashMethod=\"{}\"/>",
+            hash_algorithm.block_map_uri()
+        );
+        let content_types = "<?xml version=\"1.0\" encoding=\"UTF-8\"?><Types \
+             xmlns=\"http://schemas.openxmlformats.org/package/2006/content-types\"></Types>";
+        let mut zip = ZipWriter::new(Vec::new());
+        zip.write_file("app.exe", &[0x90; 5000], true).unwrap();
+        zip.write_file("AppxMetadata/CodeIntegrity.cat", b"catalog", true)
+            .unwrap();
+        zip.write_file(APPX_BLOCK_MAP_NAME, block_map.as_bytes(), true)
+            .unwrap();
+        zip.write_file(CONTENT_TYPES_NAME, content_types.as_bytes(), true)
+            .unwrap();
+        fs::write(path, zip.finish().unwrap()).unwrap();
+    }
+
+    /// Returns the tagged digests the signature in `data` covers.
+    ///
+    fn signed_digests(data: &[u8], hash_algorithm: HashAlgorithm) -> Vec<(String, Vec<u8>)> {
+        let archive = ZipArchive::from_bytes(data.to_owned()).unwrap();
+        let signature = archive
+            .read(archive.find(APPX_SIGNATURE_NAME).unwrap())
+            .unwrap();
+        assert!(signature.starts_with(b"PKCX"));
+
+        let start = signature.windows(4).position(|w| w == b"APPX").unwrap() + 4;
+        let digest_len = hash_algorithm.digest(&[]).len();
+        signature[start..]
+            .chunks(4 + digest_len)
+            .take_while(|chunk| chunk.len() == 4 + digest_len && chunk.starts_with(b"AX"))
+            .map(|chunk| {
+                let tag = String::from_utf8(chunk[..4].to_vec()).unwrap();
+                (tag, chunk[4..].to_vec())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn signs_packages() {
+        let dir = temp_dir("sign");
+        let certificate = certificate(&dir);
+        let path = dir.join("test.appx");
+        write_package(&path, HashAlgorithm::Sha256);
+
+        // Signing twice replaces the first signature
+        sign_package(&path, &certificate, None).unwrap();
+        sign_package(&path, &certificate, Some(HashAlgorithm::Sha256)).unwrap();
+        let data = fs::read(&path).unwrap();
+        let archive = ZipArchive::from_bytes(data.clone()).unwrap();
+        let names: Vec<_> = archive.entries().iter().map(|e| e.name.as_str()).collect();
+        assert_eq!(
+            names,
+            [
+                "app.exe",
+                "AppxMetadata/CodeIntegrity.cat",
+                APPX_BLOCK_MAP_NAME,
+                CONTENT_TYPES_NAME,
+                APPX_SIGNATURE_NAME
+            ]
+        );
+        let read = |name: &str| archive.read(archive.find(name).unwrap()).unwrap();
+        let content_types = String::from_utf8(read(CONTENT_TYPES_NAME)).unwrap();
+        assert_eq!(content_types.matches(SIGNATURE_CONTENT_TYPE).count(), 1);
+        assert_eq!(read("app.exe"), [0x90; 5000]);
+
+        // The archive without the signature: All records preceding the signature, and the
+        // central directory minus its last record, with the end record adjusted to match
+        let signature = archive.find(APPX_SIGNATURE_NAME).unwrap();
+        let records = &data[..signature.header_offset as usize];
+        let central_directory_end = data.windows(4).rposition(|w| w == b"PK\x05\x06").unwrap();
+        let central_directory_start =
+            u32::from_le_bytes(data[central_directory_end + 16..][..4].try_into().unwrap())
+                as usize;
+        let signature_record = data.windows(4).rposition(|w| w == b"PK\x01\x02").unwrap();
+        let central_directory = &data[central_directory_start..signature_record];
+        let mut trailer = central_directory.to_vec();
+        trailer.extend_from_slice(b"PK\x05\x06\0\0\0\0\x04\0\x04\0");
+        trailer.extend_from_slice(&(central_directory.len() as u32).to_le_bytes());
+        trailer.extend_from_slice(&(records.len() as u32).to_le_bytes());
+        trailer.extend_from_slice(&[0, 0]);
+
+        let digest = |data: &[u8]| HashAlgorithm::Sha256.digest(data);
+        let expected = [
+            ("AXPC", digest(records)),
+            ("AXCD", digest(&trailer)),
+            ("AXCT", digest(content_types.as_bytes())),
+            ("AXBM", digest(&read(APPX_BLOCK_MAP_NAME))),
+            ("AXCI", digest(b"catalog")),
+        ];
+        let digests = signed_digests(&data, HashAlgorithm::Sha256);
+        assert_eq!(
+            digests,
+            expected
+                .iter()
+                .map(|(tag, digest)| ((*tag).to_owned(), digest.clone()))
+                .collect::<Vec<_>>()
+        );
+
+        // Packages must be signed using the hash algorithm of their block map
+        write_package(&path, HashAlgorithm::Sha512);
+        assert!(sign_package(&path, &certificate, Some(HashAlgorithm::Sha256)).is_err());
+        sign_package(&path, &certificate, None).unwrap();
+        let digests = signed_digests(&fs::read(&path).unwrap(), HashAlgorithm::Sha512);
+        assert_eq!(digests.len(), 5);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn matches_publisher() {
+        let dir = temp_dir("publisher");
+        let certificate = certificate(&dir);
+        assert_eq!(certificate.subject(), PUBLISHER);
+
+        for publisher in [
+            PUBLISHER,
+            "CN=Contoso,O=\"Contoso, Inc.\",C=US",
+            "cn=Contoso; o=\"Contoso, Inc.\"; c=US",
+            "CN = Contoso , O = \"Contoso, Inc.\" , C = US",
+        ] {
+            assert!(certificate.matches_publisher(publisher), "{}", publisher);
+        }
+        for publisher in [
+            "CN=Contoso",
+            "CN=contoso, O=\"Contoso, Inc.\", C=US",
+            "O=\"Contoso, Inc.\", CN=Contoso, C=US",
+            "CN=Contoso, O=Contoso, Inc., C=US",
+            "CN=Contoso, O=\"Contoso, Inc.\", C=US, L=Redmond",
+            "CN=Contoso, O=\"Contoso, Inc.",
+            "",
+        ] {
+            assert!(!certificate.matches_publisher(publisher), "{}", publisher);
+        }
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
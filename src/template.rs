@@ -0,0 +1,111 @@
+//! Implements user-supplied template directories.
+//!
+//! A template directory mirrors the layout of the `data/` directory of this tool. Its
+//! files override the built-in files at the same location, or are added to the generated
+//! package. Files under `starters/<name>/` only apply to the selected starter application,
+//! and a trailing underscore is stripped from file names (e.g. `bindings/Cargo.toml_`).
+//! Placeholders are expanded in all files that are valid UTF-8. A *Cargo.toml* at the top
+//! is merged into the package manifest rather than replacing it.
+//!
+use std::{
+    fs,
+    path::{Component, Path, PathBuf},
+};
+
+use anyhow::{bail, Context};
+
+/// Environment variable holding the default template directory.
+pub(crate) const TEMPLATE_DIR_ENV: &str = "CARGO_UWP_TEMPLATE_DIR";
+
+/// Returns all files in `template_dir` that apply to `starter`, as paths relative to the
+/// package root along with their contents, expanding each `(placeholder, value)` pair of
+/// `placeholders` in text files.
+///
+pub(crate) fn files(
+    template_dir: &Path,
+    starter: &str,
+    placeholders: &[(&str, String)],
+) -> anyhow::Result<Vec<(PathBuf, Vec<u8>)>> {
+    let mut files = Vec::new();
+    collect_files(template_dir, &mut files)?;
+    let mut expanded = Vec::new();
+    for file in files {
+        let relative = file.strip_prefix(template_dir)?;
+        let destination = match destination(relative, starter) {
+            Some(destination) => destination,
+            None => continue,
+        };
+
+        let contents = fs::read(&file).with_context(|| format!("Reading {}", file.display()))?;
+        let contents = match String::from_utf8(contents) {
+            Ok(mut text) => {
+                for (placeholder, value) in placeholders {
+                    text = text.replace(placeholder, value);
+                }
+                text.into_bytes()
+            }
+            Err(e) => e.into_bytes(),
+        };
+        expanded.push((destination, contents));
+    }
+
+    Ok(expanded)
+}
+
+/// Fails if `template_dir` isn't a directory.
+///
+pub(crate) fn check_dir(template_dir: &Path) -> anyhow::Result<()> {
+    if !template_dir.is_dir() {
+        bail!(
+            "Template directory {} doesn't exist or isn't a directory",
+            template_dir.display()
+        );
+    }
+
+    Ok(())
+}
+
+/// Collects all files below `dir`, in a stable order.
+///
+fn collect_files(dir: &Path, files: &mut Vec<PathBuf>) -> anyhow::Result<()> {
+    let mut entries = fs::read_dir(dir)
+        .with_context(|| format!("Reading directory {}", dir.display()))?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<Result<Vec<_>, _>>()?;
+    entries.sort();
+    for path in entries {
+        if path.is_dir() {
+            collect_files(&path, files)?;
+        } else {
+            files.push(path);
+        }
+    }
+
+    Ok(())
+}
+
+/// Maps the path of a file relative to the template directory to its path relative to the
+/// package root, or `None` if the file belongs to a starter other than `starter`.
+///
+fn destination(relative: &Path, starter: &str) -> Option<PathBuf> {
+    let mut components = relative.components();
+    let mut destination = PathBuf::new();
+    if let Some(Component::Normal(first)) = relative.components().next() {
+        if first == "starters" {
+            components.next();
+            if components.next()?.as_os_str() != starter {
+                return None;
+            }
+        }
+    }
+    for component in components {
+        destination.push(component);
+    }
+
+    let file_name = destination.file_name()?.to_string_lossy().into_owned();
+    if let Some(stripped) = file_name.strip_suffix('_') {
+        destination.set_file_name(stripped);
+    }
+
+    Some(destination)
+}
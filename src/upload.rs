@@ -0,0 +1,67 @@
+//! Implements writing of Store upload files (.appxupload, .msixupload).
+//!
+//! An upload file is a plain ZIP archive holding an application bundle, alongside the
+//! symbol archives (.appxsym) of each architecture contained in the bundle.
+//!
+use std::{
+    fs::{self, File},
+    io::BufWriter,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{anyhow, Context};
+
+use crate::appx::{self, Format};
+use crate::bundle::BundleInfo;
+use crate::zip::ZipWriter;
+
+/// Returns the conventional upload file name
+/// `<name>_<version>_<arch1>_<arch2>_<...>.<extension>`.
+///
+pub(crate) fn upload_file_name(bundle: &BundleInfo, format: Format) -> String {
+    format!(
+        "{}_{}_{}.{}",
+        bundle.name,
+        bundle.version,
+        bundle.archs.join("_"),
+        format.upload_extension()
+    )
+}
+
+/// Writes an upload file containing `bundle` and the symbol archives `symbols` to
+/// `path`.
+///
+pub(crate) fn write_upload(
+    path: &Path,
+    bundle: &BundleInfo,
+    symbols: &[PathBuf],
+) -> anyhow::Result<()> {
+    let result = write_upload_unchecked(path, bundle, symbols);
+    if result.is_err() {
+        // Don't leave a truncated upload file behind
+        let _ = fs::remove_file(path);
+    }
+    result
+}
+
+fn write_upload_unchecked(
+    path: &Path,
+    bundle: &BundleInfo,
+    symbols: &[PathBuf],
+) -> anyhow::Result<()> {
+    let output = File::create(path).with_context(|| format!("Creating {}", path.display()))?;
+    let mut zip = ZipWriter::new(BufWriter::new(output));
+
+    // Bundles and symbol archives are compressed already, so store them as-is
+    for file in std::iter::once(&bundle.path).chain(symbols) {
+        let name = file
+            .file_name()
+            .and_then(|name| name.to_str())
+            .ok_or_else(|| anyhow!("Invalid file name {}", file.display()))?;
+        let data = fs::read(file).with_context(|| format!("Reading {}", file.display()))?;
+        zip.write_file(&appx::part_name(name), &data, false)?;
+    }
+    zip.finish()?;
+
+    Ok(())
+}
@@ -0,0 +1,887 @@
+//! A minimal ZIP archive reader and writer.
+//!
+//! Package formats used by the UWP (.appx, .appxbundle, .appxsym, .appxupload) are all
+//! ZIP archives underneath. Some of them (notably packages and bundles) place constraints
+//! on the archive that general purpose ZIP libraries don't allow to control, such as
+//! compressing file data in independently sized blocks. This writer leaves producing the
+//! (optionally compressed) file data to the caller, and only deals with the archive
+//! structure itself.
+//!
+//! ZIP64 extensions are emitted whenever sizes, offsets, or the number of entries exceed
+//! the limits of the original format.
+//!
+//! The reader supports the subset of the format produced by the writer (and tools such as
+//! *MakeAppx.exe*): Single-disk archives, with entries either stored or deflated.
+//!
+use std::{
+    borrow::Cow,
+    convert::{TryFrom, TryInto},
+    fs,
+    io::{self, Read, Write},
+    path::Path,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use flate2::{read::DeflateDecoder, write::DeflateEncoder, Compression};
+
+const LOCAL_FILE_HEADER_SIGNATURE: u32 = 0x0403_4b50;
+const CENTRAL_DIRECTORY_HEADER_SIGNATURE: u32 = 0x0201_4b50;
+const END_OF_CENTRAL_DIRECTORY_SIGNATURE: u32 = 0x0605_4b50;
+const ZIP64_END_OF_CENTRAL_DIRECTORY_SIGNATURE: u32 = 0x0606_4b50;
+const ZIP64_END_OF_CENTRAL_DIRECTORY_LOCATOR_SIGNATURE: u32 = 0x0706_4b50;
+
+const LOCAL_FILE_HEADER_SIZE: u64 = 30;
+const ZIP64_EXTRA_FIELD_ID: u16 = 0x0001;
+
+/// Highest ratio of uncompressed to compressed size Deflate can achieve.
+const MAX_DEFLATE_RATIO: u64 = 1032;
+
+const VERSION_DEFAULT: u16 = 20;
+const VERSION_ZIP64: u16 = 45;
+
+/// Compression method applied to the data of an archive entry.
+///
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Method {
+    Stored,
+    Deflated,
+}
+
+impl Method {
+    fn id(self) -> u16 {
+        match self {
+            Method::Stored => 0,
+            Method::Deflated => 8,
+        }
+    }
+}
+
+/// Describes a single archive entry, as written to the central directory.
+///
+#[derive(Clone, Debug)]
+pub(crate) struct Entry {
+    pub(crate) name: String,
+    pub(crate) method: Method,
+    pub(crate) crc32: u32,
+    pub(crate) compressed_size: u64,
+    pub(crate) uncompressed_size: u64,
+    pub(crate) header_offset: u64,
+    /// Size of the local file header, including the file name and extra field.
+    pub(crate) header_size: u64,
+    /// MS-DOS modification time and date.
+    pub(crate) dos_time: u16,
+    pub(crate) dos_date: u16,
+}
+
+/// Writes a ZIP archive to an underlying [`Write`] implementation.
+///
+/// Entries are written in the order [`ZipWriter::write_entry`] is called. The archive is
+/// incomplete until [`ZipWriter::finish`] has written the central directory.
+///
+pub(crate) struct ZipWriter<W: Write> {
+    writer: W,
+    offset: u64,
+    entries: Vec<Entry>,
+    dos_time: u16,
+    dos_date: u16,
+}
+
+impl<W: Write> ZipWriter<W> {
+    /// Constructs a new [`ZipWriter`]. All entries are time stamped with the time of
+    /// construction.
+    ///
+    pub(crate) fn new(writer: W) -> Self {
+        let (dos_date, dos_time) = dos_date_time(SystemTime::now());
+        Self {
+            writer,
+            offset: 0,
+            entries: Vec::new(),
+            dos_time,
+            dos_date,
+        }
+    }
+
+    /// Writes an archive entry. `data` holds the file contents, compressed according to
+    /// `method`. `crc32` and `uncompressed_size` describe the original (uncompressed)
+    /// file contents.
+    ///
+    pub(crate) fn write_entry(
+        &mut self,
+        name: &str,
+        method: Method,
+        crc32: u32,
+        uncompressed_size: u64,
+        data: &[u8],
+    ) -> io::Result<&Entry> {
+        let compressed_size = data.len() as u64;
+        let zip64 = needs_zip64(uncompressed_size) || needs_zip64(compressed_size);
+        let name_len = u16::try_from(name.len())
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "File name too long"))?;
+
+        let mut header = Vec::with_capacity(LOCAL_FILE_HEADER_SIZE as usize + name.len() + 20);
+        put_u32(&mut header, LOCAL_FILE_HEADER_SIGNATURE);
+        put_u16(
+            &mut header,
+            if zip64 {
+                VERSION_ZIP64
+            } else {
+                VERSION_DEFAULT
+            },
+        );
+        put_u16(&mut header, 0); // general purpose bit flag
+        put_u16(&mut header, method.id());
+        put_u16(&mut header, self.dos_time);
+        put_u16(&mut header, self.dos_date);
+        put_u32(&mut header, crc32);
+        if zip64 {
+            put_u32(&mut header, u32::MAX);
+            put_u32(&mut header, u32::MAX);
+        } else {
+            put_u32(&mut header, compressed_size as u32);
+            put_u32(&mut header, uncompressed_size as u32);
+        }
+        put_u16(&mut header, name_len);
+        put_u16(&mut header, if zip64 { 20 } else { 0 });
+        header.extend_from_slice(name.as_bytes());
+        if zip64 {
+            put_u16(&mut header, ZIP64_EXTRA_FIELD_ID);
+            put_u16(&mut header, 16);
+            put_u64(&mut header, uncompressed_size);
+            put_u64(&mut header, compressed_size);
+        }
+
+        self.writer.write_all(&header)?;
+        self.writer.write_all(data)?;
+
+        let header_offset = self.offset;
+        self.offset += header.len() as u64 + compressed_size;
+        self.entries.push(Entry {
+            name: name.to_owned(),
+            method,
+            crc32,
+            compressed_size,
+            uncompressed_size,
+            header_offset,
+            header_size: header.len() as u64,
+            dos_time: self.dos_time,
+            dos_date: self.dos_date,
+        });
+
+        Ok(self.entries.last().unwrap())
+    }
+
+    /// Writes an archive entry holding `data`, deflated as a single stream when `compress`
+    /// is `true`.
+    ///
+    pub(crate) fn write_file(
+        &mut self,
+        name: &str,
+        data: &[u8],
+        compress: bool,
+    ) -> io::Result<&Entry> {
+        let crc32 = crc32fast::hash(data);
+        if compress && !data.is_empty() {
+            let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+            encoder.write_all(data)?;
+            let compressed = encoder.finish()?;
+            self.write_entry(
+                name,
+                Method::Deflated,
+                crc32,
+                data.len() as u64,
+                &compressed,
+            )
+        } else {
+            self.write_entry(name, Method::Stored, crc32, data.len() as u64, data)
+        }
+    }
+
+    /// Copies an entry from another archive. `record` holds the local file header and
+    /// file data of `entry`, as returned by [`ZipArchive::raw_record`]. The entry keeps its
+    /// original time stamp.
+    ///
+    pub(crate) fn copy_entry(&mut self, entry: &Entry, record: &[u8]) -> io::Result<&Entry> {
+        self.writer.write_all(record)?;
+        self.entries.push(Entry {
+            header_offset: self.offset,
+            ..entry.clone()
+        });
+        self.offset += record.len() as u64;
+
+        Ok(self.entries.last().unwrap())
+    }
+
+    /// Returns the underlying writer.
+    ///
+    pub(crate) fn get_ref(&self) -> &W {
+        &self.writer
+    }
+
+    /// Returns the central directory and end of central directory record(s) that
+    /// [`ZipWriter::finish`] would write if no further entries were added.
+    ///
+    pub(crate) fn trailer(&self) -> Vec<u8> {
+        let mut trailer = central_directory(&self.entries);
+        let end = end_of_central_directory(
+            self.entries.len() as u64,
+            trailer.len() as u64,
+            self.offset,
+            self.offset + trailer.len() as u64,
+        );
+        trailer.extend_from_slice(&end);
+        trailer
+    }
+
+    /// Writes the central directory and end of central directory record(s), and returns
+    /// the underlying writer.
+    ///
+    pub(crate) fn finish(mut self) -> io::Result<W> {
+        let trailer = self.trailer();
+        self.writer.write_all(&trailer)?;
+        self.writer.flush()?;
+
+        Ok(self.writer)
+    }
+}
+
+/// A ZIP archive read into memory.
+///
+pub(crate) struct ZipArchive {
+    data: Vec<u8>,
+    entries: Vec<Entry>,
+}
+
+impl ZipArchive {
+    /// Reads the archive at `path`.
+    ///
+    pub(crate) fn open(path: &Path) -> io::Result<Self> {
+        Self::from_bytes(fs::read(path)?)
+    }
+
+    /// Parses the archive contained in `data`.
+    ///
+    pub(crate) fn from_bytes(data: Vec<u8>) -> io::Result<Self> {
+        let eocd = find_end_of_central_directory(&data)?;
+        let mut entry_count = read_u16(&data, eocd + 10)? as u64;
+        let mut central_directory_offset = read_u32(&data, eocd + 16)? as u64;
+
+        // Consult the ZIP64 end of central directory record if the locator is present
+        if eocd >= 20
+            && read_u32(&data, eocd - 20)? == ZIP64_END_OF_CENTRAL_DIRECTORY_LOCATOR_SIGNATURE
+        {
+            let zip64_eocd = to_usize(read_u64(&data, eocd - 20 + 8)?)?;
+            if read_u32(&data, zip64_eocd)? != ZIP64_END_OF_CENTRAL_DIRECTORY_SIGNATURE {
+                return Err(invalid_data(
+                    "Invalid ZIP64 end of central directory record",
+                ));
+            }
+            entry_count = read_u64(&data, zip64_eocd + 32)?;
+            central_directory_offset = read_u64(&data, zip64_eocd + 48)?;
+        }
+
+        let mut entries = Vec::new();
+        let mut pos = to_usize(central_directory_offset)?;
+        for _ in 0..entry_count {
+            if read_u32(&data, pos)? != CENTRAL_DIRECTORY_HEADER_SIGNATURE {
+                return Err(invalid_data("Invalid central directory file header"));
+            }
+            let method = match read_u16(&data, pos + 10)? {
+                0 => Method::Stored,
+                8 => Method::Deflated,
+                id => {
+                    return Err(invalid_data(&format!(
+                        "Unsupported compression method {}",
+                        id
+                    )))
+                }
+            };
+            let dos_time = read_u16(&data, pos + 12)?;
+            let dos_date = read_u16(&data, pos + 14)?;
+            let crc32 = read_u32(&data, pos + 16)?;
+            let mut compressed_size = read_u32(&data, pos + 20)? as u64;
+            let mut uncompressed_size = read_u32(&data, pos + 24)? as u64;
+            let name_len = read_u16(&data, pos + 28)? as usize;
+            let extra_len = read_u16(&data, pos + 30)? as usize;
+            let comment_len = read_u16(&data, pos + 32)? as usize;
+            let mut header_offset = read_u32(&data, pos + 42)? as u64;
+            let name = String::from_utf8_lossy(slice(&data, pos + 46, name_len)?).into_owned();
+
+            // Pick up 64-bit values from the ZIP64 extended information extra field
+            let extra = slice(&data, pos + 46 + name_len, extra_len)?;
+            let mut extra_pos = 0;
+            while extra_pos + 4 <= extra.len() {
+                let id = read_u16(extra, extra_pos)?;
+                let size = read_u16(extra, extra_pos + 2)? as usize;
+                if id == ZIP64_EXTRA_FIELD_ID {
+                    let mut field_pos = extra_pos + 4;
+                    for value in [
+                        &mut uncompressed_size,
+                        &mut compressed_size,
+                        &mut header_offset,
+                    ] {
+                        if *value == u32::MAX as u64 {
+                            *value = read_u64(extra, field_pos)?;
+                            field_pos += 8;
+                        }
+                    }
+                }
+                extra_pos += 4 + size;
+            }
+
+            // The local file header may carry a different extra field
+            let header = to_usize(header_offset)?;
+            if read_u32(&data, header)? != LOCAL_FILE_HEADER_SIGNATURE {
+                return Err(invalid_data("Invalid local file header"));
+            }
+            let header_size = LOCAL_FILE_HEADER_SIZE
+                + read_u16(&data, header + 26)? as u64
+                + read_u16(&data, header + 28)? as u64;
+
+            entries.push(Entry {
+                name,
+                method,
+                crc32,
+                compressed_size,
+                uncompressed_size,
+                header_offset,
+                header_size,
+                dos_time,
+                dos_date,
+            });
+            pos += 46 + name_len + extra_len + comment_len;
+        }
+
+        Ok(Self { data, entries })
+    }
+
+    /// Returns all entries, in the order they appear in the central directory.
+    ///
+    pub(crate) fn entries(&self) -> &[Entry] {
+        &self.entries
+    }
+
+    /// Looks up an entry by name. File names are compared case-insensitively.
+    ///
+    pub(crate) fn find(&self, name: &str) -> Option<&Entry> {
+        self.entries
+            .iter()
+            .find(|entry| entry.name.eq_ignore_ascii_case(name))
+    }
+
+    /// Returns the (possibly compressed) file data of `entry`, as stored in the archive.
+    ///
+    pub(crate) fn raw_data(&self, entry: &Entry) -> io::Result<&[u8]> {
+        slice(
+            &self.data,
+            to_usize(entry.header_offset + entry.header_size)?,
+            to_usize(entry.compressed_size)?,
+        )
+    }
+
+    /// Returns the local file header and (possibly compressed) file data of `entry`, as
+    /// stored in the archive.
+    ///
+    /// If the entry is followed by a data descriptor, the returned local file header is
+    /// amended to describe the entry by itself, and the data descriptor is dropped. The
+    /// size of the local file header is left unchanged.
+    ///
+    pub(crate) fn raw_record(&self, entry: &Entry) -> io::Result<Cow<'_, [u8]>> {
+        let record = slice(
+            &self.data,
+            to_usize(entry.header_offset)?,
+            to_usize(entry.header_size + entry.compressed_size)?,
+        )?;
+        let flags = read_u16(record, 6)?;
+        if flags & 0x0008 == 0 {
+            return Ok(Cow::Borrowed(record));
+        }
+
+        let mut record = record.to_owned();
+        record[6..8].copy_from_slice(&(flags & !0x0008).to_le_bytes());
+        record[14..18].copy_from_slice(&entry.crc32.to_le_bytes());
+
+        // Sizes go into the ZIP64 extended information extra field if there is one, and
+        // into the fixed fields otherwise
+        let name_len = read_u16(&record, 26)? as usize;
+        let extra_len = read_u16(&record, 28)? as usize;
+        let extra_start = LOCAL_FILE_HEADER_SIZE as usize + name_len;
+        let mut extra_pos = extra_start;
+        let mut zip64_field = None;
+        while extra_pos + 4 <= extra_start + extra_len {
+            let size = read_u16(&record, extra_pos + 2)? as usize;
+            if read_u16(&record, extra_pos)? == ZIP64_EXTRA_FIELD_ID && size >= 16 {
+                zip64_field = Some(extra_pos + 4);
+            }
+            extra_pos += 4 + size;
+        }
+        match zip64_field {
+            Some(field) => {
+                record[18..26].copy_from_slice(&[0xff; 8]);
+                record[field..field + 8].copy_from_slice(&entry.uncompressed_size.to_le_bytes());
+                record[field + 8..field + 16].copy_from_slice(&entry.compressed_size.to_le_bytes());
+            }
+            None if needs_zip64(entry.compressed_size) || needs_zip64(entry.uncompressed_size) => {
+                return Err(invalid_data(&format!(
+                    "Entry {} uses a data descriptor, and its local file header has no room \
+                     for its 64-bit sizes",
+                    entry.name
+                )));
+            }
+            None => {
+                record[18..22].copy_from_slice(&(entry.compressed_size as u32).to_le_bytes());
+                record[22..26].copy_from_slice(&(entry.uncompressed_size as u32).to_le_bytes());
+            }
+        }
+
+        Ok(Cow::Owned(record))
+    }
+
+    /// Returns the uncompressed file data of `entry`, verifying its checksum.
+    ///
+    pub(crate) fn read(&self, entry: &Entry) -> io::Result<Vec<u8>> {
+        let raw = self.raw_data(entry)?;
+        let data = match entry.method {
+            Method::Stored => raw.to_owned(),
+            Method::Deflated => {
+                // The declared size is only trusted as far as the compressed data can back
+                // it, and decompression stops past it
+                let capacity = entry
+                    .uncompressed_size
+                    .min((raw.len() as u64).saturating_mul(MAX_DEFLATE_RATIO));
+                let mut data = Vec::with_capacity(to_usize(capacity)?);
+                DeflateDecoder::new(raw)
+                    .take(entry.uncompressed_size.saturating_add(1))
+                    .read_to_end(&mut data)?;
+                data
+            }
+        };
+        if data.len() as u64 != entry.uncompressed_size {
+            return Err(invalid_data(&format!("Size mismatch for '{}'", entry.name)));
+        }
+        if crc32fast::hash(&data) != entry.crc32 {
+            return Err(invalid_data(&format!("CRC mismatch for '{}'", entry.name)));
+        }
+        Ok(data)
+    }
+}
+
+/// Locates the end of central directory record, scanning backwards to skip over a
+/// trailing archive comment.
+///
+fn find_end_of_central_directory(data: &[u8]) -> io::Result<usize> {
+    const MIN_SIZE: usize = 22;
+    if data.len() < MIN_SIZE {
+        return Err(invalid_data("File too small to be a ZIP archive"));
+    }
+    let lowest = data.len().saturating_sub(MIN_SIZE + u16::MAX as usize);
+    (lowest..=data.len() - MIN_SIZE)
+        .rev()
+        .find(|&pos| read_u32(data, pos).ok() == Some(END_OF_CENTRAL_DIRECTORY_SIGNATURE))
+        .ok_or_else(|| invalid_data("Missing end of central directory record"))
+}
+
+/// Serializes the central directory for `entries`.
+///
+fn central_directory(entries: &[Entry]) -> Vec<u8> {
+    let mut buffer = Vec::new();
+    for entry in entries {
+        let mut extra = Vec::new();
+        if needs_zip64(entry.uncompressed_size) {
+            put_u64(&mut extra, entry.uncompressed_size);
+        }
+        if needs_zip64(entry.compressed_size) {
+            put_u64(&mut extra, entry.compressed_size);
+        }
+        if needs_zip64(entry.header_offset) {
+            put_u64(&mut extra, entry.header_offset);
+        }
+        let zip64 = !extra.is_empty();
+
+        put_u32(&mut buffer, CENTRAL_DIRECTORY_HEADER_SIGNATURE);
+        put_u16(&mut buffer, VERSION_ZIP64); // version made by
+        put_u16(
+            &mut buffer,
+            if zip64 {
+                VERSION_ZIP64
+            } else {
+                VERSION_DEFAULT
+            },
+        );
+        put_u16(&mut buffer, 0); // general purpose bit flag
+        put_u16(&mut buffer, entry.method.id());
+        put_u16(&mut buffer, entry.dos_time);
+        put_u16(&mut buffer, entry.dos_date);
+        put_u32(&mut buffer, entry.crc32);
+        put_u32(&mut buffer, clamp_u32(entry.compressed_size));
+        put_u32(&mut buffer, clamp_u32(entry.uncompressed_size));
+        put_u16(&mut buffer, entry.name.len() as u16);
+        put_u16(&mut buffer, if zip64 { extra.len() as u16 + 4 } else { 0 });
+        put_u16(&mut buffer, 0); // file comment length
+        put_u16(&mut buffer, 0); // disk number start
+        put_u16(&mut buffer, 0); // internal file attributes
+        put_u32(&mut buffer, 0); // external file attributes
+        put_u32(&mut buffer, clamp_u32(entry.header_offset));
+        buffer.extend_from_slice(entry.name.as_bytes());
+        if zip64 {
+            put_u16(&mut buffer, ZIP64_EXTRA_FIELD_ID);
+            put_u16(&mut buffer, extra.len() as u16);
+            buffer.extend_from_slice(&extra);
+        }
+    }
+
+    buffer
+}
+
+/// Serializes the end of central directory record, preceded by the ZIP64 end of central
+/// directory record and locator, if required.
+///
+fn end_of_central_directory(
+    entry_count: u64,
+    central_directory_size: u64,
+    central_directory_offset: u64,
+    end_offset: u64,
+) -> Vec<u8> {
+    let mut buffer = Vec::new();
+    let zip64 = entry_count >= u16::MAX as u64
+        || needs_zip64(central_directory_size)
+        || needs_zip64(central_directory_offset);
+
+    if zip64 {
+        put_u32(&mut buffer, ZIP64_END_OF_CENTRAL_DIRECTORY_SIGNATURE);
+        put_u64(&mut buffer, 44); // size of the remaining record
+        put_u16(&mut buffer, VERSION_ZIP64);
+        put_u16(&mut buffer, VERSION_ZIP64);
+        put_u32(&mut buffer, 0); // number of this disk
+        put_u32(&mut buffer, 0); // disk with the start of the central directory
+        put_u64(&mut buffer, entry_count);
+        put_u64(&mut buffer, entry_count);
+        put_u64(&mut buffer, central_directory_size);
+        put_u64(&mut buffer, central_directory_offset);
+
+        put_u32(
+            &mut buffer,
+            ZIP64_END_OF_CENTRAL_DIRECTORY_LOCATOR_SIGNATURE,
+        );
+        put_u32(&mut buffer, 0); // disk with the start of the ZIP64 end of central directory
+        put_u64(&mut buffer, end_offset);
+        put_u32(&mut buffer, 1); // total number of disks
+    }
+
+    let entry_count = if entry_count >= u16::MAX as u64 {
+        u16::MAX
+    } else {
+        entry_count as u16
+    };
+    put_u32(&mut buffer, END_OF_CENTRAL_DIRECTORY_SIGNATURE);
+    put_u16(&mut buffer, 0); // number of this disk
+    put_u16(&mut buffer, 0); // disk with the start of the central directory
+    put_u16(&mut buffer, entry_count);
+    put_u16(&mut buffer, entry_count);
+    put_u32(&mut buffer, clamp_u32(central_directory_size));
+    put_u32(&mut buffer, clamp_u32(central_directory_offset));
+    put_u16(&mut buffer, 0); // comment length
+
+    buffer
+}
+
+fn needs_zip64(value: u64) -> bool {
+    value >= u32::MAX as u64
+}
+
+fn clamp_u32(value: u64) -> u32 {
+    if needs_zip64(value) {
+        u32::MAX
+    } else {
+        value as u32
+    }
+}
+
+fn slice(data: &[u8], pos: usize, len: usize) -> io::Result<&[u8]> {
+    pos.checked_add(len)
+        .and_then(|end| data.get(pos..end))
+        .ok_or_else(|| invalid_data("Unexpected end of ZIP archive"))
+}
+
+fn read_u16(data: &[u8], pos: usize) -> io::Result<u16> {
+    Ok(u16::from_le_bytes(slice(data, pos, 2)?.try_into().unwrap()))
+}
+
+fn read_u32(data: &[u8], pos: usize) -> io::Result<u32> {
+    Ok(u32::from_le_bytes(slice(data, pos, 4)?.try_into().unwrap()))
+}
+
+fn read_u64(data: &[u8], pos: usize) -> io::Result<u64> {
+    Ok(u64::from_le_bytes(slice(data, pos, 8)?.try_into().unwrap()))
+}
+
+fn to_usize(value: u64) -> io::Result<usize> {
+    usize::try_from(value).map_err(|_| invalid_data("ZIP archive too large"))
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_owned())
+}
+
+fn put_u16(buffer: &mut Vec<u8>, value: u16) {
+    buffer.extend_from_slice(&value.to_le_bytes());
+}
+
+fn put_u32(buffer: &mut Vec<u8>, value: u32) {
+    buffer.extend_from_slice(&value.to_le_bytes());
+}
+
+fn put_u64(buffer: &mut Vec<u8>, value: u64) {
+    buffer.extend_from_slice(&value.to_le_bytes());
+}
+
+/// Converts a [`SystemTime`] into an MS-DOS `(date, time)` pair (in UTC). Times outside
+/// the representable range (1980 through 2107) are clamped.
+///
+fn dos_date_time(time: SystemTime) -> (u16, u16) {
+    let secs = time
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    let days = (secs / 86_400) as i64;
+    let secs_of_day = secs % 86_400;
+
+    // Convert days since the epoch into a civil date (proleptic Gregorian calendar)
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    if year < 1980 {
+        return (0x21, 0); // 1980-01-01 00:00:00
+    }
+    if year > 2107 {
+        return (0xff9f, 0xbf7d); // 2107-12-31 23:59:58
+    }
+
+    let date = ((year - 1980) << 9) as u16 | (month << 5) as u16 | day as u16;
+    let time = ((secs_of_day / 3600) << 11) as u16
+        | (((secs_of_day % 3600) / 60) << 5) as u16
+        | ((secs_of_day % 60) / 2) as u16;
+
+    (date, time)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write_archive(files: &[(&str, &[u8], bool)]) -> Vec<u8> {
+        let mut zip = ZipWriter::new(Vec::new());
+        for (name, data, compress) in files {
+            zip.write_file(name, data, *compress).unwrap();
+        }
+        zip.finish().unwrap()
+    }
+
+    #[test]
+    fn round_trip() {
+        let files: &[(&str, &[u8], bool)] = &[
+            ("stored.txt", b"stored contents", false),
+            ("dir/deflated.txt", &[b'x'; 1000], true),
+            ("empty.txt", b"", true),
+        ];
+        let archive = ZipArchive::from_bytes(write_archive(files)).unwrap();
+
+        assert_eq!(archive.entries().len(), files.len());
+        for ((name, data, compress), entry) in files.iter().zip(archive.entries()) {
+            assert_eq!(entry.name, *name);
+            let method = if *compress && !data.is_empty() {
+                Method::Deflated
+            } else {
+                Method::Stored
+            };
+            assert_eq!(entry.method, method);
+            assert_eq!(entry.uncompressed_size, data.len() as u64);
+            assert_eq!(archive.read(entry).unwrap(), *data);
+        }
+        assert!(archive.find("DIR/Deflated.TXT").is_some());
+        assert!(archive.find("missing.txt").is_none());
+    }
+
+    #[test]
+    fn detects_corruption() {
+        let mut data = write_archive(&[("file.txt", b"contents", false)]);
+        data[LOCAL_FILE_HEADER_SIZE as usize + "file.txt".len()] ^= 1;
+        let archive = ZipArchive::from_bytes(data).unwrap();
+        assert!(archive.read(&archive.entries()[0]).is_err());
+
+        assert!(ZipArchive::from_bytes(b"not an archive".to_vec()).is_err());
+    }
+
+    #[test]
+    fn rejects_wrong_sizes() {
+        let archive =
+            ZipArchive::from_bytes(write_archive(&[("file.txt", &[b'x'; 1000], true)])).unwrap();
+        let mut entry = archive.entries()[0].clone();
+        for size in [u64::MAX, 1 << 40, 1001, 999] {
+            entry.uncompressed_size = size;
+            let error = archive.read(&entry).unwrap_err();
+            assert!(error.to_string().contains("Size mismatch"), "{}", error);
+        }
+    }
+
+    #[test]
+    fn trailer_matches_finish() {
+        let mut zip = ZipWriter::new(Vec::new());
+        zip.write_file("file.txt", b"contents", true).unwrap();
+        let records = zip.get_ref().clone();
+        let trailer = zip.trailer();
+
+        assert_eq!(zip.finish().unwrap(), [records, trailer].concat());
+    }
+
+    #[test]
+    fn copied_entries_keep_time_stamps() {
+        let mut zip = ZipWriter::new(Vec::new());
+        zip.dos_time = 0x3a85;
+        zip.dos_date = 0x50a6;
+        zip.write_file("old.txt", b"old", true).unwrap();
+        let old = ZipArchive::from_bytes(zip.finish().unwrap()).unwrap();
+
+        let mut zip = ZipWriter::new(Vec::new());
+        zip.dos_time = 0x1234;
+        zip.dos_date = 0x5678;
+        let entry = &old.entries()[0];
+        zip.copy_entry(entry, &old.raw_record(entry).unwrap())
+            .unwrap();
+        zip.write_file("new.txt", b"new", true).unwrap();
+        let data = zip.finish().unwrap();
+        let archive = ZipArchive::from_bytes(data.clone()).unwrap();
+
+        for (entry, time, date) in [
+            (&archive.entries()[0], 0x3a85, 0x50a6),
+            (&archive.entries()[1], 0x1234, 0x5678),
+        ] {
+            assert_eq!((entry.dos_time, entry.dos_date), (time, date));
+            let header = entry.header_offset as usize;
+            assert_eq!(read_u16(&data, header + 10).unwrap(), time);
+            assert_eq!(read_u16(&data, header + 12).unwrap(), date);
+        }
+        assert_eq!(archive.read(&archive.entries()[0]).unwrap(), b"old");
+    }
+
+    #[test]
+    fn raw_record_drops_data_descriptors() {
+        let data = write_archive(&[("file.txt", b"contents", true)]);
+        let archive = ZipArchive::from_bytes(data).unwrap();
+        let entry = &archive.entries()[0];
+        let record = archive.raw_record(entry).unwrap().into_owned();
+        assert!(matches!(
+            archive.raw_record(entry).unwrap(),
+            Cow::Borrowed(_)
+        ));
+
+        // Move the checksum and sizes into a data descriptor following the file data
+        let mut streamed = record.clone();
+        streamed[6] |= 0x08;
+        streamed[14..26].copy_from_slice(&[0; 12]);
+        put_u32(&mut streamed, 0x0807_4b50);
+        put_u32(&mut streamed, entry.crc32);
+        put_u32(&mut streamed, entry.compressed_size as u32);
+        put_u32(&mut streamed, entry.uncompressed_size as u32);
+        let mut zip = ZipWriter::new(Vec::new());
+        zip.copy_entry(entry, &streamed).unwrap();
+        zip.write_file("next.txt", b"next", false).unwrap();
+        let archive = ZipArchive::from_bytes(zip.finish().unwrap()).unwrap();
+
+        let entry = &archive.entries()[0];
+        assert_eq!(
+            archive.raw_record(entry).unwrap().as_ref(),
+            record.as_slice()
+        );
+        assert_eq!(archive.read(entry).unwrap(), b"contents");
+        assert_eq!(archive.read(&archive.entries()[1]).unwrap(), b"next");
+    }
+
+    #[test]
+    fn zip64_sizes() {
+        // The writer takes sizes at face value, so a large entry doesn't need large data
+        let size = 5 << 30;
+        let mut zip = ZipWriter::new(Vec::new());
+        let entry = zip
+            .write_entry("large.bin", Method::Deflated, 0x1234_5678, size, b"data")
+            .unwrap();
+        assert_eq!(entry.header_size, LOCAL_FILE_HEADER_SIZE + 9 + 20);
+        zip.write_file("small.txt", b"small", false).unwrap();
+        let data = zip.finish().unwrap();
+        assert_eq!(read_u16(&data, 4).unwrap(), VERSION_ZIP64);
+        assert_eq!(read_u32(&data, 22).unwrap(), u32::MAX);
+
+        let archive = ZipArchive::from_bytes(data).unwrap();
+        let large = &archive.entries()[0];
+        assert_eq!(large.uncompressed_size, size);
+        assert_eq!(large.compressed_size, 4);
+        assert_eq!(large.crc32, 0x1234_5678);
+        assert_eq!(archive.raw_data(large).unwrap(), b"data");
+        assert_eq!(archive.read(&archive.entries()[1]).unwrap(), b"small");
+    }
+
+    #[test]
+    fn zip64_end_of_central_directory() {
+        let count = u16::MAX as usize + 1;
+        let mut zip = ZipWriter::new(Vec::new());
+        for index in 0..count {
+            zip.write_entry(&index.to_string(), Method::Stored, 0, 0, &[])
+                .unwrap();
+        }
+        let data = zip.finish().unwrap();
+        let eocd = find_end_of_central_directory(&data).unwrap();
+        assert_eq!(read_u16(&data, eocd + 10).unwrap(), u16::MAX);
+        assert_eq!(
+            read_u32(&data, eocd - 20).unwrap(),
+            ZIP64_END_OF_CENTRAL_DIRECTORY_LOCATOR_SIGNATURE
+        );
+
+        let archive = ZipArchive::from_bytes(data).unwrap();
+        assert_eq!(archive.entries().len(), count);
+        assert_eq!(archive.entries()[count - 1].name, (count - 1).to_string());
+    }
+
+    #[test]
+    fn zip64_central_directory_fields() {
+        let entry = Entry {
+            name: "a".to_owned(),
+            method: Method::Stored,
+            crc32: 0,
+            compressed_size: 6 << 30,
+            uncompressed_size: 6 << 30,
+            header_offset: 7 << 30,
+            header_size: LOCAL_FILE_HEADER_SIZE + 1 + 20,
+            dos_time: 0,
+            dos_date: 0,
+        };
+        let record = central_directory(&[entry]);
+        assert_eq!(read_u16(&record, 6).unwrap(), VERSION_ZIP64);
+        assert_eq!(read_u32(&record, 20).unwrap(), u32::MAX);
+        assert_eq!(read_u32(&record, 24).unwrap(), u32::MAX);
+        assert_eq!(read_u32(&record, 42).unwrap(), u32::MAX);
+        assert_eq!(read_u16(&record, 30).unwrap(), 28);
+        assert_eq!(read_u16(&record, 47).unwrap(), ZIP64_EXTRA_FIELD_ID);
+        assert_eq!(read_u64(&record, 51).unwrap(), 6 << 30);
+        assert_eq!(read_u64(&record, 59).unwrap(), 6 << 30);
+        assert_eq!(read_u64(&record, 67).unwrap(), 7 << 30);
+
+        let end = end_of_central_directory(1, record.len() as u64, 8 << 30, 9 << 30);
+        assert_eq!(
+            read_u32(&end, 0).unwrap(),
+            ZIP64_END_OF_CENTRAL_DIRECTORY_SIGNATURE
+        );
+        assert_eq!(read_u64(&end, 48).unwrap(), 8 << 30);
+        assert_eq!(read_u64(&end, 64).unwrap(), 9 << 30);
+        assert_eq!(
+            read_u32(&end, 76).unwrap(),
+            END_OF_CENTRAL_DIRECTORY_SIGNATURE
+        );
+        assert_eq!(read_u32(&end, 76 + 16).unwrap(), u32::MAX);
+    }
+}